@@ -1,17 +1,1942 @@
 extern crate mesh;
 
-use std::old_io::BufferedReader;
+use std::ascii::AsciiExt;
+use std::iter::Peekable;
+use std::old_io::{BufferedReader,BufferedWriter,IoResult,Reader,Writer};
 use std::old_io::fs::File;
-use mesh::Mesh;
+use std::old_io::stdio::{stderr,stdin_raw,stdout,stdout_raw};
+use std::num::Float;
+use std::time::Duration;
+use mesh::{AmfFile, Axis, BoundsPoint, ByteOrder, DxfFile, GltfFile, Mesh, MeshError, ObjFile};
+use mesh::{Matrix4, OffFile, PlyFile, PovFile, Shortfall, StlFile, SvgFile, ThreeMfFile, Vector3D};
+use mesh::{DEGENERATE_EPSILON, SLIVER_ASPECT, stats_json};
+
+static USAGE: &'static str = "Usage: ./meshman -i <path/to/mesh> [options] [command args...]
+
+Options:
+    -i, --input FILE    mesh to read, STL, OBJ or AMF by extension; '-' reads STL
+                        from stdin, which is also used when -i is omitted
+                        and stdin is not a terminal. Repeat to merge several
+                        meshes; outputs are named after the first. STL may
+                        be gzip-compressed, whatever its extension
+        --generate cube SIZE | sphere RADIUS [SEGMENTS] |
+                   cylinder RADIUS HEIGHT [SEGMENTS]
+                        start from a cube, sphere or cylinder centered on
+                        the origin instead of reading -i, named new-cube.*
+                        and so on; round shapes have 32 segments unless
+                        given. With -i as well the files are merged in
+    -O, --output FILE   name the written files FILE, with the extension
+                        changed to suit each format, instead of
+                        new-<name>.<ext> in the current directory; every
+                        format asked for shares the one base name
+    -s, --stl           write the result as binary STL to new-<name>.stl;
+                        "-s -" writes it to stdout instead
+        --stdout        same as "-s -"
+        --gzip          compress the STL written by -s with gzip, naming
+                        the file new-<name>.stl.gz
+        --preserve-header
+                        write STL with the 80-byte header of the binary STL
+                        input rather than meshman's own
+        --amf           write the result as AMF to new-<name>.amf, a
+                        volume for each material
+    -o, --obj           write the result as Wavefront OBJ to new-<name>.obj
+        --off           write the result as OFF to new-<name>.off
+        --gltf          write the result as binary glTF to new-<name>.glb
+        --3mf           write the result as 3MF to new-<name>.3mf
+        --pov-mesh2     write the result as a POV-Ray mesh2 object to
+                        new-<name>.inc
+        --ply [ascii|binary]
+                        write the result as PLY to new-<name>.ply, ASCII
+                        unless binary is given
+        --svg [SIZE]    draw the edges of the result seen from above as
+                        SVG to new-<name>.svg, fitted to SIZE pixels
+                        (default 512)
+    -V, --verbose       print details of the mesh as it is read
+    -q, --quiet         don't print progress or warnings, only errors and
+                        the reports asked for
+        --big-endian    read binary STL with big-endian numbers, as some
+                        legacy exporters wrote it; STL output follows
+        --no-dedup      read STL as triangle soup, three vertices per
+                        facet, which is faster for big files
+        --progress      show how far through reading a binary STL is
+        --recover       keep the facets read from a truncated STL, with a
+                        warning, instead of failing
+        --threads N     use N threads for vertex transforms on large
+                        meshes (default 1)
+        --recompute-normals
+                        write facet normals computed from the vertices
+                        rather than those read from the STL input
+        --repair        before the commands, weld, orient, fill holes,
+                        remove degenerate facets and compact, reporting
+                        the vertex and facet counts after each step, and
+                        write recomputed normals
+        --repair-weld EPS
+                        weld tolerance for --repair, which it implies
+                        (default 1e-4)
+        --script FILE   run the commands in FILE, one per line with its
+                        arguments, before any given on the command line;
+                        '#' starts a comment
+        --count-only    print the number of facets in each input and stop,
+                        reading no more of an STL than it takes to count
+        --timing        report on stderr how long reading each input, each
+                        command and each write took
+        --dry-run       read the input and run the commands, then print
+                        what was done instead of writing any files
+        --min-edge EPS  after the commands, collapse edges shorter than EPS
+                        to their midpoints where that flips no facet
+        --decimate RATIO
+                        after the commands, collapse short edges until
+                        about RATIO of the facets remain
+        --hull          replace the result with its convex hull, after
+                        the commands and any decimation
+        --canonical     before writing, sort the vertices by position and
+                        the facets by their vertices, so the same geometry
+                        is always written the same way
+        --split         write each connected piece of the result as binary
+                        STL to part-0.stl, part-1.stl, ..., adding the
+                        name of each named solid to its file. If the input
+                        has several solids (several -i files, or several
+                        solids in an ASCII STL) each is written instead,
+                        put through the commands on its own
+    -v, --view          print the first few vertices and facets of the result
+        --dump          print every vertex and facet of the result
+        --info          print a summary: vertex and facet counts, size and
+                        whether the result is watertight
+        --stats-json    print counts, bounding box, volume, area and
+                        watertightness of the result as JSON
+        --bbox          print the bounding box of the result
+        --bsphere       print the center and radius of a sphere enclosing
+                        the result
+        --layers THICKNESS
+                        print how many layers of that thickness it takes to
+                        print the result, and its height
+        --volume        print the enclosed volume of the result
+        --area          print the total surface area of the result
+        --edge-stats    print the number of edges of the result and their
+                        minimum, maximum and mean length
+        --centroid      print the center of mass of the result, or the
+                        average of its vertices if it isn't watertight
+        --watertight    check that every edge is shared by exactly two facets
+        --holes         list the open boundary loops of the result and any
+                        edges shared by more than two facets
+        --check-degenerate
+                        list facets with (near) zero area
+        --check-normals compare the normals stored in the STL input, as read
+                        and before any commands run, with the ones its
+                        winding gives, and list the worst that disagree
+        --normal-tolerance DEGREES
+                        angle by which a stored normal may differ before
+                        --check-normals counts it (default 10)
+        --validate      list vertices with NaN or infinite coordinates and
+                        the facets using them
+        --drop-nan      remove facets using vertices with NaN or infinite
+                        coordinates from each input as it is read
+        --degenerate-eps EPS
+                        cross product magnitude below which a facet is
+                        degenerate (default 1e-6)
+        --quality       print a histogram of facet areas, their minimum,
+                        maximum and mean, and the number of slivers
+        --quality-bins N
+                        bins in the --quality histogram (default 10)
+        --sliver-aspect R
+                        longest edge squared over area above which a facet
+                        is a sliver (default 20)
+        --diff FILE     compare the result with the mesh in FILE, vertex by
+                        vertex and facet by facet, and exit non-zero if
+                        they differ
+        --diff-unordered
+                        compare facets by position instead, for meshes
+                        whose vertices are numbered differently
+        --diff-eps EPS  largest coordinate difference --diff allows
+                        (default 1e-5)
+        --slice-z Z     print the segments where the result crosses the
+                        plane at height Z
+        --slice-dxf Z   write the segments where the result crosses the
+                        plane at height Z as lines in a DXF drawing to
+                        new-<name>.dxf
+
+Commands:
+    assign-material Z0,Z1,ID
+                        print facets whose center lies between heights Z0
+                        and Z1 in material ID, written to AMF as a volume of
+                        its own; 0 is the default material
+    auto-orient         rotate so the side with the most area facing one way
+                        faces down, a rough guess at the best side to print
+                        on rather than the one needing the least support;
+                        follow with drop to rest it on Z=0
+    center              translate so the bounding box center is at the origin
+    compact             drop vertices that no facet uses
+    crop X,Y,Z X,Y,Z    keep only facets entirely inside the box between the
+                        two corners
+    dedup               merge vertices with identical coordinates
+    remove-degenerate   drop facets with (near) zero area, as listed by
+                        --check-degenerate
+    weld EPS            merge vertices closer than EPS to each other
+    snap GRID           round coordinates to multiples of GRID; follow with
+                        dedup to merge vertices that were nearly coincident
+    move-to min|max|center X,Y,Z
+                        translate so the bounding box point lands on X,Y,Z
+    array NX,NY,NZ SPACING
+                        replicate into a grid with SPACING between copies
+    array NX,NY,NZ SX,SY,SZ
+                        replicate into a grid, offsetting each copy from
+                        the last by SX, SY or SZ along that axis
+    drop                move along Z so the lowest point rests on Z=0
+    fill-holes          close each hole with a fan of new facets
+    flip-normals        reverse the winding of every facet
+    matrix M00,M01,...,M33
+                        apply a 4x4 transform given as 16 numbers, row by
+                        row, to each vertex as the point (x,y,z,1)
+    mirror x|y|z        negate that coordinate, mirroring across the plane
+                        through the origin perpendicular to the axis
+    offset D            move each vertex D along its normal, outward if D is
+                        positive; large offsets can make facets cross
+                        where the surface curves the other way
+    orient              flip facets as needed so neighbours agree on their
+                        winding
+    rotate-axis X,Y,Z DEGREES
+                        rotate about the axis X,Y,Z through the origin,
+                        counter-clockwise looking down the axis
+    scale SX,SY,SZ      multiply each vertex component-wise
+    scale S             scale uniformly, same as scale S,S,S
+    smooth N LAMBDA     N passes of Laplacian smoothing, moving each vertex
+                        LAMBDA of the way to the average of its neighbours
+    smooth-pinned N LAMBDA
+                        smooth, but keep vertices on open edges in place
+    subdivide           split each facet into four at its edge midpoints
+    translate X,Y,Z     add X,Y,Z to each vertex
+    units FROM,TO       convert from one unit to another by scaling, e.g.
+                        units inch,mm; units are mm, cm, m, inch and mil";
+
+// Largest coordinate difference --diff allows by default.
+const DIFF_EPSILON: f32 = 1e-5;
+// Number of differing vertices and facets --diff lists.
+const DIFF_PREVIEW: usize = 5;
+// Default angle in degrees by which --check-normals lets a stored normal
+// differ from the winding.
+const NORMAL_TOLERANCE: f32 = 10.0;
+// Number of the worst mismatched normals --check-normals lists.
+const NORMAL_PREVIEW: usize = 5;
+// Default distance below which --repair welds vertices together.
+const REPAIR_WELD_EPSILON: f32 = 1e-4;
+// Default number of segments around a generated sphere or cylinder.
+const PRIMITIVE_SEGMENTS: usize = 32;
+// Default number of bins in the --quality area histogram.
+const QUALITY_BINS: usize = 10;
+// Width in characters of the longest --quality histogram bar.
+const QUALITY_BAR: usize = 40;
+
+// println! to an arbitrary writer, so reports can be moved off stdout when
+// stdout is carrying mesh data.
+macro_rules! report {
+    ($out:expr, $($arg:tt)*) => ({
+        let _ = writeln!($out, $($arg)*);
+    })
+}
+
+// A report that -q/--quiet suppresses: progress and warnings rather than
+// output that was asked for.
+macro_rules! note {
+    ($quiet:expr, $out:expr, $($arg:tt)*) => ({
+        if !$quiet {
+            report!($out, $($arg)*);
+        }
+    })
+}
+
+trait MeshOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh;
+
+    // What the operation will do, for --dry-run.
+    fn describe(&self) -> String;
+
+    // The operation as a fixed affine transform, for operations that are
+    // one regardless of the mesh. Runs of these are composed and applied
+    // to the vertices in a single pass.
+    fn matrix(&self) -> Option<Matrix4> {
+        None
+    }
+}
+
+struct MoveToOperation {
+    point: BoundsPoint,
+    target: Vector3D,
+}
+
+impl MeshOperation for MoveToOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        mesh.move_to(self.point, self.target)
+    }
+
+    fn describe(&self) -> String {
+        format!("move {:?} of the bounding box to {:?}", self.point, self.target)
+    }
+}
+
+struct AssignMaterialOperation {
+    z0: f32,
+    z1: f32,
+    id: u32,
+}
+
+impl MeshOperation for AssignMaterialOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        mesh.assign_material(self.z0, self.z1, self.id)
+    }
+
+    fn describe(&self) -> String {
+        format!("assign material {} to facets from Z={} to Z={}", self.id, self.z0, self.z1)
+    }
+}
+
+struct AutoOrientOperation;
+
+impl MeshOperation for AutoOrientOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        let down = Vector3D::new(0.0, 0.0, -1.0);
+        mesh.rest_on(mesh.largest_flat_face_normal().unwrap_or(down))
+    }
+
+    fn describe(&self) -> String {
+        "rotate to rest on the largest flat face".to_string()
+    }
+}
+
+struct CenterOperation;
+
+impl MeshOperation for CenterOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        mesh.move_to(BoundsPoint::Center, Vector3D::new(0.0, 0.0, 0.0))
+    }
+
+    fn describe(&self) -> String {
+        "center on the origin".to_string()
+    }
+}
+
+struct CompactOperation;
+
+impl MeshOperation for CompactOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        mesh.compact()
+    }
+
+    fn describe(&self) -> String {
+        "drop unused vertices".to_string()
+    }
+}
+
+struct CropOperation {
+    min: Vector3D,
+    max: Vector3D,
+}
+
+impl MeshOperation for CropOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        mesh.crop(self.min, self.max)
+    }
+
+    fn describe(&self) -> String {
+        format!("crop to the box from {:?} to {:?}", self.min, self.max)
+    }
+}
+
+struct DedupOperation;
+
+impl MeshOperation for DedupOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        mesh.deduplicate()
+    }
+
+    fn describe(&self) -> String {
+        "merge identical vertices".to_string()
+    }
+}
+
+struct WeldOperation {
+    eps: f32,
+}
+
+impl MeshOperation for WeldOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        mesh.weld_with_tolerance(self.eps)
+    }
+
+    fn describe(&self) -> String {
+        format!("weld vertices closer than {}", self.eps)
+    }
+}
+
+struct RemoveDegenerateOperation {
+    eps: f32,
+}
+
+impl MeshOperation for RemoveDegenerateOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        mesh.remove_degenerate(self.eps)
+    }
+
+    fn describe(&self) -> String {
+        "remove degenerate facets".to_string()
+    }
+}
+
+struct SnapOperation {
+    grid: f32,
+}
+
+impl MeshOperation for SnapOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        mesh.snap(self.grid)
+    }
+
+    fn describe(&self) -> String {
+        format!("snap to a grid of {}", self.grid)
+    }
+}
+
+// How far apart array copies are: a gap between their bounding boxes, or
+// a fixed offset from one copy to the next along each axis.
+enum ArraySpacing {
+    Gap(f32),
+    Pitch(Vector3D),
+}
+
+struct ArrayOperation {
+    counts: (usize, usize, usize),
+    spacing: ArraySpacing,
+}
+
+impl MeshOperation for ArrayOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        let (nx, ny, nz) = self.counts;
+        match self.spacing {
+            ArraySpacing::Gap(gap) => mesh.array(nx, ny, nz, gap),
+            ArraySpacing::Pitch(pitch) => mesh.array_pitch(nx, ny, nz, pitch),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self.spacing {
+            ArraySpacing::Gap(gap) => format!("array {:?} copies {} apart", self.counts, gap),
+            ArraySpacing::Pitch(pitch) => format!("array {:?} copies offset by {:?}",
+                self.counts, pitch),
+        }
+    }
+}
+
+struct DropOperation;
+
+impl MeshOperation for DropOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        mesh.drop_to_plate()
+    }
+
+    fn describe(&self) -> String {
+        "drop onto Z=0".to_string()
+    }
+}
+
+struct FillHolesOperation;
+
+impl MeshOperation for FillHolesOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        mesh.fill_holes()
+    }
+
+    fn describe(&self) -> String {
+        "fill holes".to_string()
+    }
+}
+
+struct FlipNormalsOperation;
+
+impl MeshOperation for FlipNormalsOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        mesh.flip_normals()
+    }
+
+    fn describe(&self) -> String {
+        "flip normals".to_string()
+    }
+}
+
+struct OffsetOperation {
+    distance: f32,
+}
+
+impl MeshOperation for OffsetOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        mesh.offset(self.distance)
+    }
+
+    fn describe(&self) -> String {
+        format!("offset by {} along the vertex normals", self.distance)
+    }
+}
+
+struct OrientOperation;
+
+impl MeshOperation for OrientOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        mesh.orient_consistently()
+    }
+
+    fn describe(&self) -> String {
+        "make the winding consistent".to_string()
+    }
+}
+
+struct MatrixOperation {
+    matrix: Matrix4,
+}
+
+impl MeshOperation for MatrixOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        mesh.transform(&self.matrix)
+    }
+
+    fn describe(&self) -> String {
+        format!("transform by {:?}", self.matrix)
+    }
+
+    fn matrix(&self) -> Option<Matrix4> {
+        Some(self.matrix)
+    }
+}
+
+struct MirrorOperation {
+    axis: Axis,
+}
+
+impl MeshOperation for MirrorOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        mesh.mirror(self.axis)
+    }
+
+    fn describe(&self) -> String {
+        format!("mirror along {:?}", self.axis)
+    }
+
+    fn matrix(&self) -> Option<Matrix4> {
+        Some(Matrix4::reflection(self.axis))
+    }
+}
+
+struct RotateAxisOperation {
+    axis: Vector3D,
+    radians: f32,
+}
+
+impl MeshOperation for RotateAxisOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        mesh.rotate(self.axis, self.radians)
+    }
+
+    fn describe(&self) -> String {
+        format!("rotate {} degrees about {:?}", self.radians.to_degrees(), self.axis)
+    }
+
+    fn matrix(&self) -> Option<Matrix4> {
+        Some(Matrix4::rotation(self.axis, self.radians))
+    }
+}
+
+struct ScaleOperation {
+    factors: Vector3D,
+}
+
+impl MeshOperation for ScaleOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        mesh.scale(self.factors)
+    }
+
+    fn describe(&self) -> String {
+        format!("scale by {:?}", self.factors)
+    }
+
+    fn matrix(&self) -> Option<Matrix4> {
+        Some(Matrix4::scaling(self.factors))
+    }
+}
+
+struct SmoothOperation {
+    iterations: usize,
+    lambda: f32,
+    pin_boundary: bool,
+}
+
+impl MeshOperation for SmoothOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        mesh.smooth(self.iterations, self.lambda, self.pin_boundary)
+    }
+
+    fn describe(&self) -> String {
+        format!("smooth {} times by {}{}", self.iterations, self.lambda,
+                if self.pin_boundary { " with open edges pinned" } else { "" })
+    }
+}
+
+struct SubdivideOperation;
+
+impl MeshOperation for SubdivideOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        mesh.subdivide()
+    }
+
+    fn describe(&self) -> String {
+        "subdivide".to_string()
+    }
+}
+
+struct TranslateOperation {
+    offset: Vector3D,
+}
+
+impl MeshOperation for TranslateOperation {
+    fn apply(&self, mesh: &Mesh) -> Mesh {
+        mesh.translate(self.offset)
+    }
+
+    fn describe(&self) -> String {
+        format!("translate by {:?}", self.offset)
+    }
+
+    fn matrix(&self) -> Option<Matrix4> {
+        Some(Matrix4::translation(self.offset))
+    }
+}
+
+fn arg_to_floats(arg: &str) -> Result<Vec<f32>, String> {
+    let mut v: Vec<f32> = Vec::new();
+    for s in arg.split(',') {
+        match s.trim().parse() {
+            Ok(f) => v.push(f),
+            Err(_) => return Err(format!("Bad number '{}' in '{}'", s, arg)),
+        }
+    }
+    Ok(v)
+}
+
+fn arg_to_vector(arg: &str) -> Result<Vector3D, String> {
+    let v = try!(arg_to_floats(arg));
+    if v.len() != 3 {
+        return Err(format!("Vector '{}' needs exactly three components, found {}",
+            arg, v.len()));
+    }
+    Ok(Vector3D::new(v[0], v[1], v[2]))
+}
+
+// Scale factors are either one per axis or a single uniform factor.
+fn arg_to_factors(arg: &str) -> Result<Vector3D, String> {
+    let v = try!(arg_to_floats(arg));
+    match v.len() {
+        1 => Ok(Vector3D::new(v[0], v[0], v[0])),
+        3 => Ok(Vector3D::new(v[0], v[1], v[2])),
+        n => Err(format!("Scale '{}' needs one or three components, found {}", arg, n)),
+    }
+}
+
+fn arg_to_matrix(arg: &str) -> Result<Matrix4, String> {
+    let v = try!(arg_to_floats(arg));
+    if v.len() != 16 {
+        return Err(format!("Matrix '{}' needs exactly 16 components, found {}", arg, v.len()));
+    }
+    let mut m = [0.0f32; 16];
+    for (i, f) in v.iter().enumerate() {
+        m[i] = *f;
+    }
+    Ok(Matrix4::new(m))
+}
+
+fn arg_to_counts(arg: &str) -> Result<(usize, usize, usize), String> {
+    let mut v: Vec<usize> = Vec::new();
+    for s in arg.split(',') {
+        match s.trim().parse() {
+            Ok(n) => v.push(n),
+            Err(_) => return Err(format!("Bad count '{}' in '{}'", s, arg)),
+        }
+    }
+    if v.len() != 3 {
+        return Err(format!("Counts '{}' need exactly three components, found {}",
+            arg, v.len()));
+    }
+    Ok((v[0], v[1], v[2]))
+}
+
+// Parses Z0,Z1,ID for assign-material: a height range and a material id.
+fn arg_to_material_range(arg: &str) -> Result<(f32, f32, u32), String> {
+    let parts: Vec<&str> = arg.split(',').collect();
+    if parts.len() != 3 {
+        return Err(format!("Material range '{}' needs Z0,Z1,ID, found {} components",
+            arg, parts.len()));
+    }
+    let z0 = try!(arg_to_float(parts[0]));
+    let z1 = try!(arg_to_float(parts[1]));
+    match parts[2].trim().parse() {
+        Ok(id) => Ok((z0, z1, id)),
+        Err(_) => Err(format!("Bad material id '{}' in '{}'", parts[2], arg)),
+    }
+}
+
+fn arg_to_count(arg: &str) -> Result<usize, String> {
+    match arg.trim().parse() {
+        Ok(n) => Ok(n),
+        Err(_) => Err(format!("Bad count '{}'", arg)),
+    }
+}
+
+fn arg_to_float(arg: &str) -> Result<f32, String> {
+    match arg.trim().parse() {
+        Ok(f) => Ok(f),
+        Err(_) => Err(format!("Bad number '{}'", arg)),
+    }
+}
+
+fn arg_to_bounds_point(arg: &str) -> Result<BoundsPoint, String> {
+    match arg {
+        "min" => Ok(BoundsPoint::Min),
+        "max" => Ok(BoundsPoint::Max),
+        "center" => Ok(BoundsPoint::Center),
+        _ => Err(format!("Unknown bounding box point '{}', expected min, max or center", arg)),
+    }
+}
+
+fn arg_to_axis(arg: &str) -> Result<Axis, String> {
+    match arg {
+        "x" => Ok(Axis::X),
+        "y" => Ok(Axis::Y),
+        "z" => Ok(Axis::Z),
+        _ => Err(format!("Unknown axis '{}', expected x, y or z", arg)),
+    }
+}
+
+// Millimetres per unit.
+fn unit_size(unit: &str) -> Result<f32, String> {
+    match unit {
+        "mm" => Ok(1.0),
+        "cm" => Ok(10.0),
+        "m" => Ok(1000.0),
+        "inch" => Ok(25.4),
+        "mil" => Ok(0.0254),
+        _ => Err(format!("Unknown unit '{}', expected mm, cm, m, inch or mil", unit)),
+    }
+}
+
+// Parses FROM,TO into the factor that converts lengths in FROM to TO.
+fn arg_to_unit_factor(arg: &str) -> Result<f32, String> {
+    let units: Vec<&str> = arg.split(',').map(|u| u.trim()).collect();
+    if units.len() != 2 {
+        return Err(format!("Expected two units like inch,mm but got '{}'", arg));
+    }
+    Ok(try!(unit_size(units[0])) / try!(unit_size(units[1])))
+}
+
+// Takes the next command argument, naming the command and the missing
+// argument if the command line ran out.
+fn next_arg<'a, I: Iterator<Item=&'a String>>(args: &mut I, cmd: &str, what: &str)
+        -> Result<&'a str, String> {
+    match args.next() {
+        Some(a) => Ok(a.as_slice()),
+        None => Err(format!("{} requires {}", cmd, what)),
+    }
+}
+
+// Builds the mesh for --generate from the shape and its sizes that follow
+// it: cube SIZE, sphere RADIUS [SEGMENTS] or cylinder RADIUS HEIGHT
+// [SEGMENTS]. The mesh is named after its shape.
+fn generate<'a, I: Iterator<Item=&'a String>>(args: &mut Peekable<I>) -> Result<Mesh, String> {
+    let shape = try!(next_arg(args, "--generate", "a shape"));
+    let segments = |args: &mut Peekable<I>| -> Result<usize, String> {
+        match args.peek().and_then(|a| a.parse().ok()) {
+            Some(n) if n >= 3 => {
+                args.next();
+                Ok(n)
+            }
+            Some(n) => Err(format!("--generate {} needs at least 3 segments, got {}", shape, n)),
+            None => Ok(PRIMITIVE_SEGMENTS),
+        }
+    };
+    let mesh = match shape {
+        "cube" => {
+            let size = try!(arg_to_float(try!(next_arg(args, "--generate cube", "a size"))));
+            Mesh::cube(size)
+        }
+        "sphere" => {
+            let radius = try!(arg_to_float(
+                try!(next_arg(args, "--generate sphere", "a radius"))));
+            Mesh::sphere(radius, try!(segments(args)))
+        }
+        "cylinder" => {
+            let radius = try!(arg_to_float(
+                try!(next_arg(args, "--generate cylinder", "a radius"))));
+            let height = try!(arg_to_float(
+                try!(next_arg(args, "--generate cylinder", "a height"))));
+            Mesh::cylinder(radius, height, try!(segments(args)))
+        }
+        _ => return Err(format!("Unknown shape: {}", shape)),
+    };
+    Ok(mesh.with_name(shape))
+}
+
+fn parse_commands(args: &[String], quiet: bool) -> Result<Vec<Box<MeshOperation>>, String> {
+    let mut operations: Vec<Box<MeshOperation>> = Vec::new();
+    let mut cmds = args.iter();
+    while let Some(cmd) = cmds.next() {
+        match cmd.as_slice() {
+            "move-to" => {
+                let point = try!(arg_to_bounds_point(
+                    try!(next_arg(&mut cmds, "move-to", "a bounding box point"))));
+                let target = try!(arg_to_vector(
+                    try!(next_arg(&mut cmds, "move-to", "a target vector"))));
+                operations.push(Box::new(MoveToOperation { point: point, target: target }));
+            }
+            "assign-material" => {
+                let (z0, z1, id) = try!(arg_to_material_range(
+                    try!(next_arg(&mut cmds, "assign-material", "a height range and id"))));
+                operations.push(Box::new(AssignMaterialOperation { z0: z0, z1: z1, id: id }));
+            }
+            "auto-orient" => operations.push(Box::new(AutoOrientOperation)),
+            "center" => operations.push(Box::new(CenterOperation)),
+            "compact" => operations.push(Box::new(CompactOperation)),
+            "crop" => {
+                let min = try!(arg_to_vector(
+                    try!(next_arg(&mut cmds, "crop", "a minimum corner"))));
+                let max = try!(arg_to_vector(
+                    try!(next_arg(&mut cmds, "crop", "a maximum corner"))));
+                if min.x() > max.x() || min.y() > max.y() || min.z() > max.z() {
+                    return Err(format!("crop box is inside out: {:?} to {:?}", min, max));
+                }
+                operations.push(Box::new(CropOperation { min: min, max: max }));
+            }
+            "dedup" => operations.push(Box::new(DedupOperation)),
+            "remove-degenerate" => {
+                operations.push(Box::new(RemoveDegenerateOperation { eps: DEGENERATE_EPSILON }))
+            }
+            "weld" => {
+                let eps = try!(arg_to_float(
+                    try!(next_arg(&mut cmds, "weld", "a tolerance"))));
+                if eps <= 0.0 {
+                    return Err(format!("weld tolerance must be positive, got {}", eps));
+                }
+                operations.push(Box::new(WeldOperation { eps: eps }));
+            }
+            "snap" => {
+                let grid = try!(arg_to_float(
+                    try!(next_arg(&mut cmds, "snap", "a grid size"))));
+                if grid <= 0.0 {
+                    return Err(format!("snap grid must be positive, got {}", grid));
+                }
+                operations.push(Box::new(SnapOperation { grid: grid }));
+            }
+            "array" => {
+                let counts = try!(arg_to_counts(
+                    try!(next_arg(&mut cmds, "array", "grid counts"))));
+                let arg = try!(next_arg(&mut cmds, "array", "a spacing"));
+                let spacing = if arg.contains(",") {
+                    ArraySpacing::Pitch(try!(arg_to_vector(arg)))
+                } else {
+                    ArraySpacing::Gap(try!(arg_to_float(arg)))
+                };
+                operations.push(Box::new(ArrayOperation { counts: counts, spacing: spacing }));
+            }
+            "drop" => operations.push(Box::new(DropOperation)),
+            "fill-holes" => operations.push(Box::new(FillHolesOperation)),
+            "flip-normals" => operations.push(Box::new(FlipNormalsOperation)),
+            "offset" => {
+                let distance = try!(arg_to_float(
+                    try!(next_arg(&mut cmds, "offset", "a distance"))));
+                operations.push(Box::new(OffsetOperation { distance: distance }));
+            }
+            "orient" => operations.push(Box::new(OrientOperation)),
+            "matrix" => {
+                let matrix = try!(arg_to_matrix(
+                    try!(next_arg(&mut cmds, "matrix", "16 matrix components"))));
+                if matrix.determinant() == 0.0 {
+                    note!(quiet, stderr(),
+                        "Warning: matrix {:?} is singular and will flatten the mesh", matrix);
+                }
+                operations.push(Box::new(MatrixOperation { matrix: matrix }));
+            }
+            "mirror" => {
+                let axis = try!(arg_to_axis(
+                    try!(next_arg(&mut cmds, "mirror", "an axis"))));
+                operations.push(Box::new(MirrorOperation { axis: axis }));
+            }
+            "rotate-axis" => {
+                let axis = try!(arg_to_vector(
+                    try!(next_arg(&mut cmds, "rotate-axis", "an axis vector"))));
+                let degrees = try!(arg_to_float(
+                    try!(next_arg(&mut cmds, "rotate-axis", "an angle in degrees"))));
+                if axis.length() == 0.0 {
+                    return Err("rotate-axis needs a non-zero axis".to_string());
+                }
+                operations.push(Box::new(RotateAxisOperation {
+                    axis: axis,
+                    radians: degrees.to_radians(),
+                }));
+            }
+            "scale" => {
+                let factors = try!(arg_to_factors(
+                    try!(next_arg(&mut cmds, "scale", "a factor or vector"))));
+                operations.push(Box::new(ScaleOperation { factors: factors }));
+            }
+            "units" => {
+                let f = try!(arg_to_unit_factor(
+                    try!(next_arg(&mut cmds, "units", "the units to convert between"))));
+                operations.push(Box::new(ScaleOperation { factors: Vector3D::new(f, f, f) }));
+            }
+            "smooth" | "smooth-pinned" => {
+                let iterations = try!(arg_to_count(
+                    try!(next_arg(&mut cmds, cmd.as_slice(), "an iteration count"))));
+                let lambda = try!(arg_to_float(
+                    try!(next_arg(&mut cmds, cmd.as_slice(), "a lambda"))));
+                operations.push(Box::new(SmoothOperation {
+                    iterations: iterations,
+                    lambda: lambda,
+                    pin_boundary: cmd.as_slice() == "smooth-pinned",
+                }));
+            }
+            "subdivide" => operations.push(Box::new(SubdivideOperation)),
+            "translate" => {
+                let offset = try!(arg_to_vector(
+                    try!(next_arg(&mut cmds, "translate", "a vector"))));
+                operations.push(Box::new(TranslateOperation { offset: offset }));
+            }
+            _ => return Err(format!("Unknown command: {}", cmd)),
+        }
+    }
+    Ok(operations)
+}
+
+// Reads a script of commands, one command and its arguments per line, as
+// they would be given on the command line. Text after '#' is a comment and
+// blank lines are skipped. Errors name the line they were found on.
+fn read_script(path: &str, quiet: bool) -> Result<Vec<Box<MeshOperation>>, String> {
+    let file = match File::open(&Path::new(path)) {
+        Ok(f) => f,
+        Err(e) => return Err(format!("{}: {}", path, e)),
+    };
+    let mut operations: Vec<Box<MeshOperation>> = Vec::new();
+    for (n, line) in BufferedReader::new(file).lines().enumerate() {
+        let line = match line {
+            Ok(l) => l,
+            Err(e) => return Err(format!("{}: {}", path, e)),
+        };
+        let code = line.as_slice().split('#').next().unwrap_or("");
+        let args: Vec<String> = code.words().map(|w| w.to_string()).collect();
+        if args.is_empty() {
+            continue;
+        }
+        match parse_commands(args.as_slice(), quiet) {
+            Ok(ops) => operations.extend(ops.into_iter()),
+            Err(e) => return Err(format!("{}:{}: {}", path, n + 1, e)),
+        }
+    }
+    Ok(operations)
+}
+
+// Applies the operations in order. Consecutive operations that are plain
+// affine transforms are composed into one matrix first, so a chain like
+// rotate-axis, scale, translate touches each vertex once. A lone transform
+// runs as itself.
+fn run_operations(mesh: Mesh, operations: &[Box<MeshOperation>], timing: bool) -> Mesh {
+    let mut mesh = mesh;
+    let mut pending: Vec<&Box<MeshOperation>> = Vec::new();
+    let flush = |mesh: Mesh, pending: &mut Vec<&Box<MeshOperation>>| -> Mesh {
+        let out = match pending.len() {
+            0 => mesh,
+            1 => timed(timing, pending[0].describe().as_slice(), || pending[0].apply(&mesh)),
+            _ => {
+                let what: Vec<String> = pending.iter().map(|op| op.describe()).collect();
+                timed(timing, what.connect(", then ").as_slice(), || {
+                    let m = pending.iter().fold(Matrix4::identity(), |acc, op| {
+                        op.matrix().unwrap().mul(&acc)
+                    });
+                    mesh.transform(&m)
+                })
+            }
+        };
+        pending.clear();
+        out
+    };
+    for op in operations.iter() {
+        if op.matrix().is_some() {
+            pending.push(op);
+        } else {
+            mesh = flush(mesh, &mut pending);
+            mesh = timed(timing, op.describe().as_slice(), || op.apply(&mesh));
+        }
+    }
+    flush(mesh, &mut pending)
+}
+
+// The --repair pipeline: the cleanup commands in the order that lets each
+// build on the last. Normals are recomputed when the result is written.
+fn repair_operations(weld_eps: f32) -> Vec<Box<MeshOperation>> {
+    let mut operations: Vec<Box<MeshOperation>> = Vec::new();
+    operations.push(Box::new(WeldOperation { eps: weld_eps }));
+    operations.push(Box::new(OrientOperation));
+    operations.push(Box::new(FillHolesOperation));
+    operations.push(Box::new(RemoveDegenerateOperation { eps: DEGENERATE_EPSILON }));
+    operations.push(Box::new(CompactOperation));
+    operations
+}
+
+// Runs the --repair pipeline, reporting the size of the mesh after each
+// stage and whether it is watertight before and after.
+fn repair<W: Writer>(mesh: Mesh, weld_eps: f32, out: &mut W) -> Mesh {
+    let watertight = |m: &Mesh| if m.is_watertight() { "watertight" } else { "not watertight" };
+    report!(out, "Repair: {} vertices, {} facets, {}",
+        mesh.vertex_count(), mesh.facet_count(), watertight(&mesh));
+    let mut mesh = mesh;
+    for op in repair_operations(weld_eps).iter() {
+        mesh = op.apply(&mesh);
+        report!(out, "  {}: {} vertices, {} facets", op.describe(),
+            mesh.vertex_count(), mesh.facet_count());
+    }
+    report!(out, "Repaired: {} vertices, {} facets, {}",
+        mesh.vertex_count(), mesh.facet_count(), watertight(&mesh));
+    mesh
+}
+
+// Runs f and, if timing is on, reports on stderr how long it took.
+fn timed<T, F: FnOnce() -> T>(timing: bool, what: &str, f: F) -> T {
+    if !timing {
+        return f();
+    }
+    let mut result = None;
+    let elapsed = Duration::span(|| result = Some(f()));
+    report!(stderr(), "Time: {}: {} ms", what,
+        elapsed.num_microseconds().unwrap_or(0) as f64 / 1000.0);
+    result.unwrap()
+}
+
+// The output formats. Every format asked for is written to the same base
+// name with its own extension.
+#[derive(Copy)]
+enum Format {
+    Stl,
+    StlGzip,
+    Amf,
+    Obj,
+    Off,
+    Gltf,
+    ThreeMf,
+    Pov,
+    Ply(bool),
+    Svg(u32),
+    // The slice at a height rather than the mesh itself.
+    SliceDxf(f32),
+}
+
+impl Format {
+    fn extension(&self) -> &'static str {
+        match *self {
+            Format::Stl => "stl",
+            Format::StlGzip => "stl.gz",
+            Format::Amf => "amf",
+            Format::Obj => "obj",
+            Format::Off => "off",
+            Format::Gltf => "glb",
+            Format::ThreeMf => "3mf",
+            Format::Pov => "inc",
+            Format::Ply(_) => "ply",
+            Format::Svg(_) => "svg",
+            Format::SliceDxf(_) => "dxf",
+        }
+    }
+}
+
+// The name output files share before their extension, new-<name> after the
+// input file, e.g. new-part for /some/dir/part.stl, written to the current
+// directory. Meshes read from stdin come out as new-mesh. With -O the
+// given path is used instead, less any extension.
+fn output_base(input: &str, output: Option<&str>) -> String {
+    // x.stl.gz is named after x, as x.stl is.
+    let strip_gz = |path: Path| {
+        if path.extension_str() == Some("gz") { path.with_extension("") } else { path }
+    };
+    if let Some(o) = output {
+        return format!("{}", strip_gz(Path::new(o)).with_extension("").display());
+    }
+    let path = strip_gz(Path::new(input));
+    let stem = if input == "-" { "mesh" } else { path.filestem_str().unwrap_or("mesh") };
+    format!("new-{}", stem)
+}
+
+// Writes the mesh in one format to base plus the format's extension and
+// returns the name of the file written.
+fn write_format(mesh: &Mesh, base: &str, format: Format, recompute_normals: bool,
+                preserve_header: bool) -> IoResult<String> {
+    let file = format!("{}.{}", base, format.extension());
+    let path = file.as_slice();
+    try!(match format {
+        Format::Stl => StlFile::write_binary_file(mesh, path, recompute_normals, preserve_header),
+        Format::StlGzip => StlFile::write_gzip_file(mesh, path, recompute_normals,
+                                                    preserve_header),
+        Format::Amf => AmfFile::write(mesh, path),
+        Format::Obj => ObjFile::write(mesh, path, recompute_normals),
+        Format::Off => OffFile::write(mesh, path),
+        Format::Gltf => GltfFile::write(mesh, path),
+        Format::ThreeMf => ThreeMfFile::write(mesh, path),
+        Format::Pov => PovFile::write_mesh2(mesh, path),
+        Format::Ply(binary) => PlyFile::write(mesh, path, binary),
+        Format::Svg(size) => SvgFile::write(mesh, path, size),
+        Format::SliceDxf(z) => DxfFile::write_segments(mesh.slice_z(z).as_slice(), path),
+    });
+    Ok(file)
+}
+
+// Keeps letters, digits, '-' and '_' of a mesh name for use in a file
+// name, replacing anything else with '_'.
+fn file_safe(name: &str) -> String {
+    name.chars().map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect()
+}
+
+// Reports an error on stderr and makes the process exit with a non-zero
+// status once main returns.
+fn fail(msg: &str) {
+    report!(stderr(), "{}", msg);
+    std::os::set_exit_status(1);
+}
+
+// Reads one mesh, from stdin for "-" and otherwise from a file whose
+// extension picks the format. stdin can't be seeked, but Mesh::read only
+// ever reads forward so the header sniffing works the same as for a file.
+// dedup and order only apply to STL; see StlFile::read_solids. Only ASCII
+// STL can hold more than one solid. With recover, a damaged STL gives what
+// could be read of it and says what was left out; see
+// StlFile::read_solids_recover.
+fn read_input(input: &str, dedup: bool, order: ByteOrder, recover: bool)
+        -> Result<(Vec<Mesh>, Option<Shortfall>), MeshError> {
+    if input == "-" {
+        return read_stl(&mut BufferedReader::new(stdin_raw()), dedup, order, recover);
+    }
+    let meshfile = match File::open(&Path::new(input)) {
+        Ok(f) => f,
+        Err(e) => return Err(MeshError::Io(e.to_string())),
+    };
+    let ext = Path::new(input).extension_str().unwrap_or("").to_ascii_lowercase();
+    match ext.as_slice() {
+        "obj" => ObjFile::read(&mut BufferedReader::new(meshfile)).map(|m| (vec![m], None)),
+        "amf" => AmfFile::read(&mut BufferedReader::new(meshfile)).map(|m| (vec![m], None)),
+        _ => read_stl(&mut BufferedReader::new(meshfile), dedup, order, recover),
+    }
+}
+
+fn read_stl<R: Reader>(r: &mut BufferedReader<R>, dedup: bool, order: ByteOrder, recover: bool)
+        -> Result<(Vec<Mesh>, Option<Shortfall>), MeshError> {
+    if recover {
+        StlFile::read_solids_recover(r, dedup, order)
+    } else {
+        StlFile::read_solids(r, dedup, order).map(|solids| (solids, None))
+    }
+}
+
+// Counts the facets of one input for --count-only. STL is counted without
+// being built into a mesh; other formats have to be read in full.
+fn count_facets(input: &str, dedup: bool, order: ByteOrder) -> Result<usize, MeshError> {
+    if input == "-" {
+        return StlFile::count_facets(&mut BufferedReader::new(stdin_raw()), order);
+    }
+    let ext = Path::new(input).extension_str().unwrap_or("").to_ascii_lowercase();
+    match ext.as_slice() {
+        "obj" | "amf" => {
+            let (parts, _) = try!(read_input(input, dedup, order, false));
+            Ok(parts.iter().fold(0, |n, m| n + m.facet_count()))
+        }
+        _ => match File::open(&Path::new(input)) {
+            Ok(f) => StlFile::count_facets(&mut BufferedReader::new(f), order),
+            Err(e) => Err(MeshError::Io(e.to_string())),
+        },
+    }
+}
 
 fn main() {
     let args = std::os::args();
-    let meshname = args.as_slice().get(1).expect(
-        "Usage: ./meshman <path/to/mesh>"
-    ).as_slice();
-    let meshfile = match File::open(&Path::new(meshname)) {
-        Ok(f) => f,
-        Err(e) => panic!("file error: {}", e),
+    let mut input_files: Vec<String> = Vec::new();
+    let mut generated: Option<Mesh> = None;
+    let mut output: Option<String> = None;
+    let mut quiet = false;
+    let mut dedup = true;
+    let mut byte_order = ByteOrder::Little;
+    let mut dry_run = false;
+    let mut recover = false;
+    let mut timing = false;
+    let mut count_only = false;
+    let mut write_stl = false;
+    let mut gzip = false;
+    let mut preserve_header = false;
+    let mut hull = false;
+    let mut canonical = false;
+    let mut slice_z: Option<f32> = None;
+    let mut slice_dxf: Option<f32> = None;
+    let mut stl_to_stdout = false;
+    let mut write_amf = false;
+    let mut write_obj = false;
+    let mut write_off = false;
+    let mut write_gltf = false;
+    let mut write_3mf = false;
+    let mut write_pov = false;
+    let mut write_ply = false;
+    let mut ply_binary = false;
+    let mut write_svg = false;
+    let mut svg_size = 512;
+    let mut recompute_normals = false;
+    let mut repair_weld: Option<f32> = None;
+    let mut split = false;
+    let mut view = false;
+    let mut dump = false;
+    let mut show_info = false;
+    let mut show_stats_json = false;
+    let mut show_bbox = false;
+    let mut show_bsphere = false;
+    let mut layer_thickness: Option<f32> = None;
+    let mut show_volume = false;
+    let mut show_area = false;
+    let mut show_centroid = false;
+    let mut check_watertight = false;
+    let mut check_holes = false;
+    let mut check_degenerate = false;
+    let mut validate = false;
+    let mut check_normals = false;
+    let mut normal_tolerance = NORMAL_TOLERANCE;
+    let mut drop_nan = false;
+    let mut degenerate_eps = DEGENERATE_EPSILON;
+    let mut show_quality = false;
+    let mut quality_bins = QUALITY_BINS;
+    let mut sliver_aspect = SLIVER_ASPECT;
+    let mut diff_file: Option<String> = None;
+    let mut diff_unordered = false;
+    let mut diff_eps = DIFF_EPSILON;
+    let mut decimate_ratio: Option<f32> = None;
+    let mut min_edge: Option<f32> = None;
+    let mut show_edge_stats = false;
+    let mut script: Option<String> = None;
+    let mut free: Vec<String> = Vec::new();
+
+    let mut it = args.iter().skip(1).peekable();
+    while let Some(arg) = it.next() {
+        match arg.as_slice() {
+            "-i" | "--input" => {
+                if let Some(f) = it.next() {
+                    input_files.push(f.clone());
+                }
+            }
+            "--generate" => {
+                match generate(&mut it) {
+                    Ok(m) => generated = Some(m),
+                    Err(e) => {
+                        fail(e.as_slice());
+                        return;
+                    }
+                }
+            }
+            "-O" | "--output" => {
+                if let Some(f) = it.next() {
+                    output = Some(f.clone());
+                }
+            }
+            "-s" | "--stl" => {
+                write_stl = true;
+                if it.peek().map_or(false, |a| a.as_slice() == "-") {
+                    it.next();
+                    stl_to_stdout = true;
+                }
+            }
+            "--stdout" => {
+                write_stl = true;
+                stl_to_stdout = true;
+            }
+            "--gzip" => gzip = true,
+            "--preserve-header" => preserve_header = true,
+            "--amf" => write_amf = true,
+            "-o" | "--obj" => write_obj = true,
+            "--off" => write_off = true,
+            "--gltf" => write_gltf = true,
+            "--3mf" => write_3mf = true,
+            "--pov-mesh2" => write_pov = true,
+            "--ply" => {
+                write_ply = true;
+                match it.peek().map(|a| a.as_slice()) {
+                    Some("ascii") => { it.next(); }
+                    Some("binary") => {
+                        it.next();
+                        ply_binary = true;
+                    }
+                    _ => {}
+                }
+            }
+            "--svg" => {
+                write_svg = true;
+                if let Some(size) = it.peek().and_then(|a| a.parse().ok()) {
+                    it.next();
+                    svg_size = size;
+                }
+            }
+            "-V" | "--verbose" => mesh::set_verbose(true),
+            "-q" | "--quiet" => quiet = true,
+            "--progress" => mesh::set_progress(true),
+            "--recover" => recover = true,
+            "--no-dedup" => dedup = false,
+            "--big-endian" => byte_order = ByteOrder::Big,
+            "--dry-run" => dry_run = true,
+            "--timing" => timing = true,
+            "--count-only" => count_only = true,
+            "--recompute-normals" => recompute_normals = true,
+            "--repair" => {
+                if repair_weld.is_none() {
+                    repair_weld = Some(REPAIR_WELD_EPSILON);
+                }
+            }
+            "--repair-weld" => {
+                match next_arg(&mut it, "--repair-weld", "a tolerance").and_then(arg_to_float) {
+                    Ok(eps) if eps > 0.0 => repair_weld = Some(eps),
+                    Ok(eps) => {
+                        fail(format!("--repair-weld tolerance must be positive, got {}", eps)
+                            .as_slice());
+                        return;
+                    }
+                    Err(e) => {
+                        fail(e.as_slice());
+                        return;
+                    }
+                }
+            }
+            "--hull" => hull = true,
+            "--canonical" => canonical = true,
+            "--split" => split = true,
+            "-v" | "--view" => view = true,
+            "--dump" => dump = true,
+            "--info" => show_info = true,
+            "--stats-json" => show_stats_json = true,
+            "--bbox" => show_bbox = true,
+            "--bsphere" => show_bsphere = true,
+            "--layers" => {
+                match next_arg(&mut it, "--layers", "a thickness").and_then(arg_to_float) {
+                    Ok(t) if t > 0.0 => layer_thickness = Some(t),
+                    Ok(t) => {
+                        fail(format!("--layers thickness must be positive, got {}", t).as_slice());
+                        return;
+                    }
+                    Err(e) => {
+                        fail(e.as_slice());
+                        return;
+                    }
+                }
+            }
+            "--volume" => show_volume = true,
+            "--area" => show_area = true,
+            "--centroid" => show_centroid = true,
+            "--watertight" => check_watertight = true,
+            "--holes" => check_holes = true,
+            "--check-degenerate" => check_degenerate = true,
+            "--validate" => validate = true,
+            "--check-normals" => check_normals = true,
+            "--normal-tolerance" => {
+                match next_arg(&mut it, "--normal-tolerance", "an angle").and_then(arg_to_float) {
+                    Ok(a) => normal_tolerance = a,
+                    Err(e) => {
+                        fail(e.as_slice());
+                        return;
+                    }
+                }
+            }
+            "--drop-nan" => drop_nan = true,
+            "--degenerate-eps" => {
+                match next_arg(&mut it, "--degenerate-eps", "a value").and_then(arg_to_float) {
+                    Ok(e) => degenerate_eps = e,
+                    Err(e) => {
+                        fail(e.as_slice());
+                        return;
+                    }
+                }
+            }
+            "--quality" => show_quality = true,
+            "--quality-bins" => {
+                match next_arg(&mut it, "--quality-bins", "a bin count").and_then(arg_to_count) {
+                    Ok(n) if n > 0 => quality_bins = n,
+                    Ok(_) => {
+                        fail("--quality-bins needs at least one bin");
+                        return;
+                    }
+                    Err(e) => {
+                        fail(e.as_slice());
+                        return;
+                    }
+                }
+            }
+            "--sliver-aspect" => {
+                match next_arg(&mut it, "--sliver-aspect", "a ratio").and_then(arg_to_float) {
+                    Ok(r) => sliver_aspect = r,
+                    Err(e) => {
+                        fail(e.as_slice());
+                        return;
+                    }
+                }
+            }
+            "--diff" => {
+                match next_arg(&mut it, "--diff", "a mesh to compare with") {
+                    Ok(f) => diff_file = Some(f.to_string()),
+                    Err(e) => {
+                        fail(e.as_slice());
+                        return;
+                    }
+                }
+            }
+            "--script" => {
+                match next_arg(&mut it, "--script", "a file of commands") {
+                    Ok(f) => script = Some(f.to_string()),
+                    Err(e) => {
+                        fail(e.as_slice());
+                        return;
+                    }
+                }
+            }
+            "--diff-unordered" => diff_unordered = true,
+            "--diff-eps" => {
+                match next_arg(&mut it, "--diff-eps", "a value").and_then(arg_to_float) {
+                    Ok(e) => diff_eps = e,
+                    Err(e) => {
+                        fail(e.as_slice());
+                        return;
+                    }
+                }
+            }
+            "--slice-z" => {
+                match next_arg(&mut it, "--slice-z", "a height").and_then(arg_to_float) {
+                    Ok(z) => slice_z = Some(z),
+                    Err(e) => {
+                        fail(e.as_slice());
+                        return;
+                    }
+                }
+            }
+            "--slice-dxf" => {
+                match next_arg(&mut it, "--slice-dxf", "a height").and_then(arg_to_float) {
+                    Ok(z) => slice_dxf = Some(z),
+                    Err(e) => {
+                        fail(e.as_slice());
+                        return;
+                    }
+                }
+            }
+            "--threads" => {
+                match next_arg(&mut it, "--threads", "a thread count").and_then(arg_to_count) {
+                    Ok(n) if n > 0 => mesh::set_threads(n),
+                    Ok(_) => {
+                        fail("--threads needs at least one thread");
+                        return;
+                    }
+                    Err(e) => {
+                        fail(e.as_slice());
+                        return;
+                    }
+                }
+            }
+            "--min-edge" => {
+                match next_arg(&mut it, "--min-edge", "a length").and_then(arg_to_float) {
+                    Ok(eps) if eps > 0.0 => min_edge = Some(eps),
+                    Ok(eps) => {
+                        fail(format!("--min-edge length must be positive, got {}", eps).as_slice());
+                        return;
+                    }
+                    Err(e) => {
+                        fail(e.as_slice());
+                        return;
+                    }
+                }
+            }
+            "--edge-stats" => show_edge_stats = true,
+            "--decimate" => {
+                match next_arg(&mut it, "--decimate", "a ratio").and_then(arg_to_float) {
+                    Ok(r) if r > 0.0 && r <= 1.0 => decimate_ratio = Some(r),
+                    Ok(r) => {
+                        fail(format!("--decimate ratio must be in (0, 1], got {}", r).as_slice());
+                        return;
+                    }
+                    Err(e) => {
+                        fail(e.as_slice());
+                        return;
+                    }
+                }
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => free.push(arg.clone()),
+        }
+    }
+
+    if input_files.is_empty() && generated.is_none() {
+        if stdin_raw().isatty() {
+            fail(USAGE);
+            return;
+        }
+        input_files.push("-".to_string());
+    }
+
+    if count_only {
+        if let Some(ref m) = generated {
+            println!("{}: {} facets", m.name().unwrap_or("mesh"), m.facet_count());
+        }
+        for input_file in input_files.iter() {
+            match count_facets(input_file.as_slice(), dedup, byte_order) {
+                Ok(n) => println!("{}: {} facets", input_file, n),
+                Err(e) => fail(format!("{}: {}", input_file, e).as_slice()),
+            }
+        }
+        return;
+    }
+
+    // Commands from a script run before those on the command line.
+    let mut operations = match script {
+        Some(ref path) => match read_script(path.as_slice(), quiet) {
+            Ok(ops) => ops,
+            Err(e) => {
+                fail(e.as_slice());
+                return;
+            }
+        },
+        None => Vec::new(),
     };
-    let mesh = Mesh::read(&mut BufferedReader::new(meshfile));
+    match parse_commands(free.as_slice(), quiet) {
+        Ok(ops) => operations.extend(ops.into_iter()),
+        Err(e) => {
+            fail(e.as_slice());
+            return;
+        }
+    }
+
+    // With the STL going to stdout everything else is reported on stderr.
+    let mut out = if stl_to_stdout { stderr() } else { stdout() };
+
+    let mut mesh = Mesh::new();
+    let mut solids: Vec<Mesh> = Vec::new();
+    // A generated mesh comes first, as if it were the first input file.
+    if let Some(ref m) = generated {
+        input_files.insert(0, m.name().unwrap_or("mesh").to_string());
+    }
+    for (i, input_file) in input_files.iter().enumerate() {
+        let parts = if i == 0 && generated.is_some() {
+            vec![generated.take().unwrap()]
+        } else {
+            let what = format!("read {}", input_file);
+            match timed(timing, what.as_slice(),
+                    || read_input(input_file.as_slice(), dedup, byte_order, recover)) {
+                Ok((parts, shortfall)) => {
+                    if let Some(s) = shortfall {
+                        note!(quiet, out, "Warning: {}: {}", input_file, s);
+                    }
+                    parts
+                }
+                Err(e) => {
+                    fail(format!("{}: {}", input_file, e).as_slice());
+                    return;
+                }
+            }
+        };
+        let parts: Vec<Mesh> = if drop_nan {
+            parts.into_iter().map(|m| {
+                if !m.has_nan() {
+                    return m;
+                }
+                let cleaned = m.drop_invalid();
+                note!(quiet, out, "Warning: dropped {} facets with invalid coordinates from {}",
+                    m.facet_count() - cleaned.facet_count(), input_file);
+                cleaned
+            }).collect()
+        } else {
+            parts
+        };
+        if parts.iter().all(|m| m.is_empty()) {
+            note!(quiet, out, "Warning: {} contains no facets", input_file);
+        }
+        // Solids without a name of their own are named after their file.
+        let stem = Path::new(input_file.as_slice()).filestem_str().unwrap_or("mesh").to_string();
+        let parts: Vec<Mesh> = parts.into_iter().map(|m| {
+            if m.name().is_some() || input_file.as_slice() == "-" {
+                m
+            } else {
+                m.with_name(stem.as_slice())
+            }
+        }).collect();
+        for m in parts.iter() {
+            mesh = mesh.merge(m);
+        }
+        if split {
+            solids.extend(parts.into_iter());
+        }
+    }
+    let input_file = input_files[0].as_slice();
+    let output_file = output.as_ref().map(|o| o.as_slice());
+    let (mesh_vertices, mesh_facets) = (mesh.vertex_count(), mesh.facet_count());
+    // Stored normals are checked as read, since the point is to catch the
+    // exporter that wrote them; commands only carry them along or drop them.
+    let normal_mismatches = if check_normals {
+        let stored = mesh.facets().iter().filter(|f| f.stored_normal().is_some()).count();
+        Some((mesh.normal_mismatches(normal_tolerance.to_radians()), stored))
+    } else {
+        None
+    };
+
+    if repair_weld.is_some() {
+        recompute_normals = true;
+    }
+    let process = |mesh: Mesh| -> Mesh {
+        let mesh = match repair_weld {
+            Some(eps) => {
+                let mut w = if stl_to_stdout { stderr() } else { stdout() };
+                timed(timing, "repair", || repair(mesh, eps, &mut w))
+            }
+            None => mesh,
+        };
+        let mut changed = run_operations(mesh, operations.as_slice(), timing);
+        if let Some(eps) = min_edge {
+            changed = timed(timing, "collapse short edges", || changed.collapse_short_edges(eps));
+        }
+        if let Some(ratio) = decimate_ratio {
+            changed = timed(timing, "decimate", || changed.decimate(ratio));
+        }
+        if hull {
+            changed = timed(timing, "convex hull", || changed.convex_hull());
+        }
+        if canonical {
+            changed = timed(timing, "canonical order", || changed.canonicalize());
+        }
+        changed
+    };
+    let changed_mesh = process(mesh);
+
+    if view {
+        report!(out, "{:?}", changed_mesh);
+    }
+
+    if dump {
+        if let Err(e) = changed_mesh.dump(&mut out) {
+            fail(format!("write error: {}", e).as_slice());
+            return;
+        }
+    }
+
+    if show_info {
+        report!(out, "Vertices:   {}", changed_mesh.vertex_count());
+        report!(out, "Facets:     {}", changed_mesh.facet_count());
+        match changed_mesh.dimensions() {
+            Some(size) => report!(out, "Size:       {:?}", size),
+            None => report!(out, "Size:       mesh is empty"),
+        }
+        report!(out, "Watertight: {}", if changed_mesh.is_watertight() { "yes" } else { "no" });
+    }
+
+    if show_stats_json {
+        report!(out, "{}", stats_json(&changed_mesh));
+    }
+
+    if show_bbox {
+        match (changed_mesh.bounding_box(), changed_mesh.dimensions()) {
+            (Some((min, max)), Some(size)) => {
+                report!(out, "Bounding box:");
+                report!(out, "  min:  {:?}", min);
+                report!(out, "  max:  {:?}", max);
+                report!(out, "  size: {:?}", size);
+            }
+            _ => report!(out, "Bounding box: mesh is empty"),
+        }
+    }
+
+    if show_bsphere {
+        if changed_mesh.vertex_count() == 0 {
+            report!(out, "Bounding sphere: mesh is empty");
+        } else {
+            let (center, radius) = changed_mesh.bounding_sphere();
+            report!(out, "Bounding sphere:");
+            report!(out, "  center: {:?}", center);
+            report!(out, "  radius: {}", radius);
+        }
+    }
+
+    if let Some(thickness) = layer_thickness {
+        match (changed_mesh.layer_count(thickness), changed_mesh.dimensions()) {
+            (Some(layers), Some(size)) => {
+                report!(out, "Layers: {} of {} (height {})", layers, thickness, size.z);
+            }
+            _ => {
+                fail("--layers: mesh is empty");
+                return;
+            }
+        }
+    }
+
+    if show_volume {
+        if !changed_mesh.is_watertight() {
+            note!(quiet, out, "Warning: mesh is not watertight, volume is not meaningful");
+        }
+        report!(out, "Volume: {}", changed_mesh.volume().abs());
+    }
+
+    if show_area {
+        report!(out, "Surface area: {}", changed_mesh.surface_area());
+    }
+
+    if show_edge_stats {
+        let lengths = changed_mesh.edge_lengths();
+        if lengths.is_empty() {
+            report!(out, "Edge lengths: mesh is empty");
+        } else {
+            let min = lengths.iter().fold(Float::infinity(), |m: f32, &l| m.min(l));
+            let max = lengths.iter().fold(Float::neg_infinity(), |m: f32, &l| m.max(l));
+            let mean = lengths.iter().fold(0.0f64, |t, &l| t + l as f64) / lengths.len() as f64;
+            report!(out, "Edge lengths: {} edges, min {}, max {}, mean {}",
+                lengths.len(), min, max, mean);
+        }
+    }
+
+    if show_centroid {
+        match changed_mesh.centroid() {
+            Some(c) => report!(out, "Centroid: {:?}", c),
+            None => report!(out, "Centroid: mesh is empty"),
+        }
+    }
+
+    if check_watertight {
+        let edges = changed_mesh.non_manifold_edges();
+        if changed_mesh.is_watertight() {
+            report!(out, "Watertight: PASS");
+        } else {
+            report!(out, "Watertight: FAIL ({} problem edges)", edges.len());
+            for &(a, b) in edges.iter() {
+                report!(out, "  {}-{}", a, b);
+            }
+        }
+    }
+
+    if check_holes {
+        let loops = changed_mesh.boundary_loops();
+        report!(out, "Holes: {}", loops.len());
+        for lp in loops.iter() {
+            report!(out, "  {:?}", lp);
+        }
+        let overshared = changed_mesh.overshared_edges();
+        if !overshared.is_empty() {
+            report!(out, "Edges shared by more than two facets: {}", overshared.len());
+            for &(a, b) in overshared.iter() {
+                report!(out, "  {}-{}", a, b);
+            }
+        }
+    }
+
+    if check_degenerate {
+        let degenerate = changed_mesh.degenerate_facets(degenerate_eps);
+        report!(out, "Degenerate facets: {}", degenerate.len());
+        if !degenerate.is_empty() {
+            report!(out, "  {:?}", degenerate);
+        }
+    }
+
+    if show_quality {
+        let areas = changed_mesh.facet_areas();
+        if areas.is_empty() {
+            report!(out, "Facet areas: mesh is empty");
+        } else {
+            let min = areas.iter().fold(Float::infinity(), |m: f32, &a| m.min(a));
+            let max = areas.iter().fold(Float::neg_infinity(), |m: f32, &a| m.max(a));
+            let mean = areas.iter().fold(0.0f64, |t, &a| t + a as f64) / areas.len() as f64;
+            report!(out, "Facet areas: min {}, max {}, mean {}", min, max, mean);
+            let width = (max - min) / quality_bins as f32;
+            let mut counts: Vec<usize> = (0..quality_bins).map(|_| 0).collect();
+            for &a in areas.iter() {
+                let bin = if width > 0.0 { ((a - min) / width) as usize } else { 0 };
+                counts[std::cmp::min(bin, quality_bins - 1)] += 1;
+            }
+            let most = *counts.iter().max().unwrap();
+            for (i, &n) in counts.iter().enumerate() {
+                let bar: String = (0..n * QUALITY_BAR / most).map(|_| '#').collect();
+                report!(out, "  {:>12} - {:<12} {:>8} {}",
+                    min + width * i as f32, min + width * (i + 1) as f32, n, bar);
+            }
+        }
+        let slivers = changed_mesh.sliver_facets(sliver_aspect);
+        report!(out, "Slivers (aspect ratio above {}): {}", sliver_aspect, slivers.len());
+    }
+
+    if let Some((ref mismatches, stored)) = normal_mismatches {
+        report!(out, "Normal mismatches: {} of {} stored normals more than {} degrees off",
+            mismatches.len(), stored, normal_tolerance);
+        for &(i, angle) in mismatches.iter().take(NORMAL_PREVIEW) {
+            report!(out, "  facet {}: {} degrees", i, angle.to_degrees());
+        }
+    }
+
+    if validate {
+        let invalid = changed_mesh.invalid_vertices();
+        report!(out, "Invalid vertices: {}", invalid.len());
+        for &i in invalid.iter() {
+            let users: Vec<usize> = changed_mesh.facets().iter().enumerate()
+                .filter(|&(_, f)| f.v1() == i || f.v2() == i || f.v3() == i)
+                .map(|(n, _)| n)
+                .collect();
+            report!(out, "  vertex {}: {:?} used by facets {:?}",
+                i, changed_mesh.vertices()[i], users);
+        }
+    }
+
+    if let Some(ref other_file) = diff_file {
+        let other = match read_input(other_file.as_slice(), dedup, byte_order, false) {
+            Ok((parts, _)) => parts.iter().fold(Mesh::new(), |all, m| all.merge(m)),
+            Err(e) => {
+                fail(format!("{}: {}", other_file, e).as_slice());
+                return;
+            }
+        };
+        let mut same = true;
+        if changed_mesh.facet_count() != other.facet_count() {
+            report!(out, "Facets: {} vs {}", changed_mesh.facet_count(), other.facet_count());
+            same = false;
+        }
+        let facets = if diff_unordered {
+            changed_mesh.differing_facets(&other, diff_eps)
+        } else {
+            if changed_mesh.vertex_count() != other.vertex_count() {
+                report!(out, "Vertices: {} vs {}", changed_mesh.vertex_count(), other.vertex_count());
+                same = false;
+            }
+            let (vertices, facets) = changed_mesh.differing_vertices(&other, diff_eps);
+            if !vertices.is_empty() {
+                report!(out, "Differing vertices: {}", vertices.len());
+                same = false;
+            }
+            for &i in vertices.iter().take(DIFF_PREVIEW) {
+                report!(out, "  vertex {}: {:?} vs {:?}", i,
+                    changed_mesh.vertices()[i], other.vertices()[i]);
+            }
+            facets
+        };
+        if !facets.is_empty() {
+            report!(out, "Differing facets: {}", facets.len());
+            same = false;
+        }
+        for &i in facets.iter().take(DIFF_PREVIEW) {
+            report!(out, "  facet {}: {:?}", i, changed_mesh.facets()[i]);
+        }
+        report!(out, "Diff: {}", if same { "same" } else { "different" });
+        if !same {
+            std::os::set_exit_status(1);
+        }
+    }
+
+    if let Some(z) = slice_z {
+        let segments = changed_mesh.slice_z(z);
+        report!(out, "Slice at Z={}: {} segments", z, segments.len());
+        for &(a, b) in segments.iter() {
+            report!(out, "  {:?} - {:?}", a, b);
+        }
+    }
+
+    if dry_run {
+        report!(out, "Read {} vertices, {} facets", mesh_vertices, mesh_facets);
+        if let Some(eps) = repair_weld {
+            for op in repair_operations(eps).iter() {
+                report!(out, "  repair: {}", op.describe());
+            }
+        }
+        for (i, op) in operations.iter().enumerate() {
+            report!(out, "{:>3}. {}", i + 1, op.describe());
+        }
+        let mut step = operations.len();
+        if let Some(eps) = min_edge {
+            step += 1;
+            report!(out, "{:>3}. collapse edges shorter than {}", step, eps);
+        }
+        if let Some(ratio) = decimate_ratio {
+            step += 1;
+            report!(out, "{:>3}. decimate to {} of the facets", step, ratio);
+        }
+        report!(out, "Result: {} vertices, {} facets",
+            changed_mesh.vertex_count(), changed_mesh.facet_count());
+        return;
+    }
+
+    if write_stl && stl_to_stdout {
+        let mut w = BufferedWriter::new(stdout_raw());
+        let written = timed(timing, "write STL to stdout", || if gzip {
+            StlFile::write_gzip(&changed_mesh, &mut w, recompute_normals, preserve_header)
+        } else {
+            StlFile::write_binary(&changed_mesh, &mut w, recompute_normals, preserve_header)
+        });
+        if let Err(e) = written {
+            fail(format!("write error: {}", e).as_slice());
+            return;
+        }
+    }
+
+    let mut formats: Vec<Format> = Vec::new();
+    if write_stl && !stl_to_stdout {
+        formats.push(if gzip { Format::StlGzip } else { Format::Stl });
+    }
+    if write_amf { formats.push(Format::Amf); }
+    if write_obj { formats.push(Format::Obj); }
+    if write_off { formats.push(Format::Off); }
+    if write_gltf { formats.push(Format::Gltf); }
+    if write_3mf { formats.push(Format::ThreeMf); }
+    if write_pov { formats.push(Format::Pov); }
+    if write_ply { formats.push(Format::Ply(ply_binary)); }
+    if write_svg { formats.push(Format::Svg(svg_size)); }
+    if let Some(z) = slice_dxf { formats.push(Format::SliceDxf(z)); }
+    let base = output_base(input_file, output_file);
+    for &format in formats.iter() {
+        let what = format!("write {}.{}", base, format.extension());
+        match timed(timing, what.as_slice(),
+                || write_format(&changed_mesh, base.as_slice(), format, recompute_normals,
+                                preserve_header)) {
+            Ok(file) => note!(quiet, out, "Wrote {}", file),
+            Err(e) => {
+                fail(format!("{}.{}: {}", base, format.extension(), e).as_slice());
+                return;
+            }
+        }
+    }
+
+    if split {
+        let parts = if solids.len() > 1 {
+            solids.into_iter().map(|m| process(m)).collect()
+        } else {
+            changed_mesh.connected_components()
+        };
+        for (i, part) in parts.iter().enumerate() {
+            let part_file = match part.name() {
+                Some(name) => format!("part-{}-{}.stl", i, file_safe(name)),
+                None => format!("part-{}.stl", i),
+            };
+            let what = format!("write {}", part_file);
+            match timed(timing, what.as_slice(),
+                    || StlFile::write_binary_file(part, part_file.as_slice(), recompute_normals,
+                                                  preserve_header)) {
+                Ok(()) => note!(quiet, out, "Wrote {}", part_file),
+                Err(e) => {
+                    fail(format!("{}: {}", part_file, e).as_slice());
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::arg_to_factors;
+    use mesh::Vector3D;
+
+    #[test]
+    fn scale_takes_a_single_uniform_factor() {
+        assert_eq!(arg_to_factors("2"), Ok(Vector3D::new(2.0, 2.0, 2.0)));
+    }
+
+    #[test]
+    fn scale_takes_one_factor_per_axis() {
+        assert_eq!(arg_to_factors("1,2,3"), Ok(Vector3D::new(1.0, 2.0, 3.0)));
+        assert!(arg_to_factors("2,2").is_err());
+    }
 }
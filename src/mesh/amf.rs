@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+use std::old_io::{BufferedReader,BufferedWriter,IoResult,Reader,Writer};
+use std::old_io::fs::File;
+
+use super::{ByteOrder, Facet, Mesh, MeshError, Vector3D};
+use inflate;
+use threemf::escape;
+
+pub struct AmfFile;
+
+impl AmfFile {
+    // Reads the vertices and triangles of every <mesh> in an AMF document.
+    // Triangle indices are local to their mesh, so each mesh's are rebased
+    // past the vertices read before it. AMF is often compressed, so gzip
+    // data and ZIP archives (using their first entry) are unpacked first.
+    pub fn read<R: Reader>(r: &mut BufferedReader<R>) -> Result<Mesh, MeshError> {
+        let mut data = match r.read_to_end() {
+            Ok(d) => d,
+            Err(e) => return Err(MeshError::Malformed(format!("read error: {}", e))),
+        };
+        if inflate::is_gzip(data.as_slice()) {
+            data = try!(inflate::gunzip(data.as_slice()).map_err(MeshError::Malformed));
+        } else if inflate::is_zip(data.as_slice()) {
+            data = try!(inflate::unzip_first(data.as_slice()).map_err(MeshError::Malformed));
+        }
+        let xml = match String::from_utf8(data) {
+            Ok(s) => s,
+            Err(_) => return Err(MeshError::Malformed("AMF is not valid UTF-8".to_string())),
+        };
+        let mesh = try!(AmfFile::parse(xml.as_slice()).map_err(MeshError::Malformed));
+        diag!("Facets: {}", mesh.facets.len());
+        diag!("Vertices: {}", mesh.vertices.len());
+        Ok(mesh)
+    }
+
+    // Walks the tags in document order, keeping the text of the innermost
+    // leaf element. Only the elements that carry geometry are looked at,
+    // along with the material of each volume; metadata, colors and the
+    // material definitions are skipped.
+    fn parse(xml: &str) -> Result<Mesh, String> {
+        let mut vertices: Vec<Vector3D> = Vec::new();
+        let mut facets: Vec<Facet> = Vec::new();
+        let mut base = 0;
+        let mut material = 0;
+        let mut text = "";
+        let mut c = [0.0f32; 3];
+        let mut v = [0usize; 3];
+        let mut rest = xml;
+        while let Some(open) = rest.find('<') {
+            let close = match rest[open..].find('>') {
+                Some(i) => open + i,
+                None => return Err("unterminated tag".to_string()),
+            };
+            let tag = &rest[open + 1..close];
+            let prev_text = text;
+            text = &rest[close + 1..];
+            text = &text[..text.find('<').unwrap_or(text.len())];
+            rest = &rest[close + 1..];
+            if tag.starts_with("?") || tag.starts_with("!") || tag.ends_with("/") {
+                continue;
+            }
+            let name = tag.split(|c: char| c.is_whitespace()).next().unwrap_or("");
+            match name {
+                "mesh" => base = vertices.len(),
+                "volume" => material = attribute(tag, "materialid")
+                    .and_then(|id| id.parse().ok()).unwrap_or(0),
+                "/x" | "/y" | "/z" => {
+                    let i = match name { "/x" => 0, "/y" => 1, _ => 2 };
+                    c[i] = match prev_text.trim().parse::<f32>() {
+                        Ok(f) => f,
+                        Err(_) => return Err(format!("bad coordinate '{}'", prev_text.trim())),
+                    };
+                }
+                "/v1" | "/v2" | "/v3" => {
+                    let i = match name { "/v1" => 0, "/v2" => 1, _ => 2 };
+                    v[i] = match prev_text.trim().parse::<usize>() {
+                        Ok(n) => n,
+                        Err(_) => return Err(format!("bad vertex index '{}'", prev_text.trim())),
+                    };
+                }
+                "vertex" => c = [0.0; 3],
+                "/vertex" => vertices.push(Vector3D { x: c[0], y: c[1], z: c[2] }),
+                "/triangle" => {
+                    let idx = [v[0] + base, v[1] + base, v[2] + base];
+                    if idx.iter().any(|&i| i >= vertices.len()) {
+                        return Err(format!("triangle {} {} {} refers to a missing vertex",
+                            v[0], v[1], v[2]));
+                    }
+                    let f = Facet::new(idx[0], idx[1], idx[2]);
+                    facets.push(Facet { material: material, ..f });
+                }
+                _ => {}
+            }
+        }
+        Ok(Mesh {
+            vertices: vertices,
+            facets: facets,
+            header: None,
+            byte_order: ByteOrder::Little,
+            name: None,
+            colors: None,
+        })
+    }
+
+    // Writes the mesh as a single uncompressed AMF object. Its facets are
+    // split into one volume per material, with a material definition for
+    // each one used other than the default, 0, whose volume has no
+    // materialid as AMF reserves 0. A facet with a color in its STL
+    // attribute word (see Facet::color) gets it as the triangle's color.
+    pub fn write(mesh: &Mesh, path: &str) -> IoResult<()> {
+        let file = try!(File::create(&Path::new(path)));
+        let mut w = BufferedWriter::new(file);
+        let mut volumes: BTreeMap<u32, Vec<&Facet>> = BTreeMap::new();
+        for f in mesh.facets.iter() {
+            volumes.entry(f.material).get().unwrap_or_else(|v| v.insert(Vec::new())).push(f);
+        }
+        try!(w.write_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+        try!(w.write_str("<amf unit=\"millimeter\">\n"));
+        for &id in volumes.keys().filter(|&&id| id != 0) {
+            try!(write!(&mut w, "  <material id=\"{0}\">\
+                <metadata type=\"name\">material {0}</metadata></material>\n", id));
+        }
+        try!(write!(&mut w, "  <object id=\"0\">\n    <metadata type=\"name\">{}</metadata>\n",
+            escape(mesh.label())));
+        try!(w.write_str("    <mesh>\n      <vertices>\n"));
+        for v in mesh.vertices.iter() {
+            try!(write!(&mut w, "        <vertex><coordinates>\
+                <x>{}</x><y>{}</y><z>{}</z></coordinates></vertex>\n", v.x, v.y, v.z));
+        }
+        try!(w.write_str("      </vertices>\n"));
+        for (&id, facets) in volumes.iter() {
+            if id == 0 {
+                try!(w.write_str("      <volume>\n"));
+            } else {
+                try!(write!(&mut w, "      <volume materialid=\"{}\">\n", id));
+            }
+            for f in facets.iter() {
+                try!(w.write_str("        <triangle>"));
+                if let Some(c) = f.color() {
+                    try!(write!(&mut w, "<color><r>{}</r><g>{}</g><b>{}</b></color>",
+                        c.r as f32 / 255.0, c.g as f32 / 255.0, c.b as f32 / 255.0));
+                }
+                try!(write!(&mut w, "<v1>{}</v1><v2>{}</v2><v3>{}</v3></triangle>\n",
+                    f.v1, f.v2, f.v3));
+            }
+            try!(w.write_str("      </volume>\n"));
+        }
+        try!(w.write_str("    </mesh>\n  </object>\n</amf>\n"));
+        w.flush()
+    }
+}
+
+// The value of a name="value" attribute in the text of a tag.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let key = format!("{}=\"", name);
+    tag.find(key.as_slice()).and_then(|i| {
+        let value = &tag[i + key.len()..];
+        value.find('"').map(|end| &value[..end])
+    })
+}
@@ -0,0 +1,218 @@
+// Encoder for raw DEFLATE streams (RFC 1951) and the gzip wrapper, the
+// writing side of inflate.rs. Matches are found with hash chains and coded
+// with the fixed Huffman tables, which keeps it short and still does well
+// on STL data since every vertex is repeated in several facets.
+
+use std::cmp::min;
+
+use inflate::{DIST_BASE, DIST_EXTRA, LENGTH_BASE, LENGTH_EXTRA};
+
+const WINDOW: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+// How many earlier positions are tried for each match, trading ratio for
+// speed.
+const MAX_CHAIN: usize = 64;
+const HASH_SIZE: usize = 1 << 15;
+
+struct BitWriter {
+    out: Vec<u8>,
+    buf: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    // DEFLATE packs values starting at the least significant bit...
+    fn put(&mut self, value: u32, n: u32) {
+        self.buf |= value << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.buf as u8);
+            self.buf >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // ...but Huffman codes starting at their most significant bit.
+    fn put_code(&mut self, code: u32, n: u32) {
+        let mut rev = 0;
+        for i in 0..n {
+            rev |= ((code >> i) & 1) << (n - 1 - i);
+        }
+        self.put(rev, n);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buf as u8);
+        }
+        self.out
+    }
+}
+
+// Writes a literal/length symbol with the fixed code from RFC 1951 3.2.6.
+fn put_symbol(bits: &mut BitWriter, sym: u32) {
+    match sym {
+        0...143 => bits.put_code(0x30 + sym, 8),
+        144...255 => bits.put_code(0x190 + sym - 144, 9),
+        256...279 => bits.put_code(sym - 256, 7),
+        _ => bits.put_code(0xc0 + sym - 280, 8),
+    }
+}
+
+fn put_match(bits: &mut BitWriter, len: usize, dist: usize) {
+    let l = (0..29).rev().find(|&i| LENGTH_BASE[i] as usize <= len).unwrap();
+    put_symbol(bits, 257 + l as u32);
+    bits.put((len - LENGTH_BASE[l] as usize) as u32, LENGTH_EXTRA[l] as u32);
+    let d = (0..30).rev().find(|&i| DIST_BASE[i] as usize <= dist).unwrap();
+    bits.put_code(d as u32, 5);
+    bits.put((dist - DIST_BASE[d] as usize) as u32, DIST_EXTRA[d] as u32);
+}
+
+// Compresses data as a single fixed-code block. head holds the latest
+// position for each hash of three bytes and prev links every position to
+// the one before it with the same hash, for the last WINDOW positions.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut bits = BitWriter { out: Vec::new(), buf: 0, count: 0 };
+    bits.put(1, 1);     // last block
+    bits.put(1, 2);     // fixed Huffman codes
+    let hash = |i: usize| -> usize {
+        ((data[i] as usize) << 10 ^ (data[i + 1] as usize) << 5 ^ data[i + 2] as usize)
+            & (HASH_SIZE - 1)
+    };
+    let mut head: Vec<Option<usize>> = (0..HASH_SIZE).map(|_| None).collect();
+    let mut prev: Vec<Option<usize>> = (0..WINDOW).map(|_| None).collect();
+    let mut i = 0;
+    while i < data.len() {
+        let mut best_len = 0;
+        let mut best_dist = 0;
+        if i + MIN_MATCH <= data.len() {
+            let max_len = min(MAX_MATCH, data.len() - i);
+            let mut candidate = head[hash(i)];
+            let mut chain = 0;
+            while let Some(j) = candidate {
+                if i - j > WINDOW || chain == MAX_CHAIN {
+                    break;
+                }
+                let mut len = 0;
+                while len < max_len && data[j + len] == data[i + len] {
+                    len += 1;
+                }
+                if len > best_len {
+                    best_len = len;
+                    best_dist = i - j;
+                    if len == max_len {
+                        break;
+                    }
+                }
+                candidate = prev[j % WINDOW];
+                chain += 1;
+            }
+        }
+        let step = if best_len >= MIN_MATCH {
+            put_match(&mut bits, best_len, best_dist);
+            best_len
+        } else {
+            put_symbol(&mut bits, data[i] as u32);
+            1
+        };
+        for k in i..i + step {
+            if k + MIN_MATCH <= data.len() {
+                let h = hash(k);
+                prev[k % WINDOW] = head[h];
+                head[h] = Some(k);
+            }
+        }
+        i += step;
+    }
+    put_symbol(&mut bits, 256);
+    bits.finish()
+}
+
+// Wraps data in a single-member gzip file (RFC 1952) with no name or
+// timestamp.
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    out.push_all(deflate(data).as_slice());
+    for &v in [crc32(data), data.len() as u32].iter() {
+        for k in 0..4 {
+            out.push((v >> (8 * k)) as u8);
+        }
+    }
+    out
+}
+
+// CRC-32 (IEEE 802.3) as gzip and ZIP require, computed bitwise.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &b in data.iter() {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, deflate, gzip};
+    use inflate::{gunzip, inflate};
+
+    // Bytes from a fixed linear congruential sequence, which deflate can't
+    // find matches in.
+    fn noise(n: usize, seed: u64) -> Vec<u8> {
+        let mut s = seed;
+        (0..n).map(|_| {
+            s = (s * 1103515245 + 12345) % (1 << 31);
+            (s >> 16) as u8
+        }).collect()
+    }
+
+    fn round_trip(data: &[u8]) -> Vec<u8> {
+        inflate(deflate(data).as_slice()).unwrap()
+    }
+
+    #[test]
+    fn empty_and_short_input_round_trip() {
+        assert_eq!(round_trip(&[]), vec![]);
+        assert_eq!(round_trip(b"a"), b"a".to_vec());
+        assert_eq!(round_trip(b"solid cube"), b"solid cube".to_vec());
+    }
+
+    #[test]
+    fn repetitive_input_past_the_window_round_trips() {
+        let periodic: Vec<u8> = (0..100000).map(|i| (i % 251) as u8).collect();
+        let compressed = deflate(periodic.as_slice());
+        assert!(compressed.len() < periodic.len() / 20);
+        assert_eq!(inflate(compressed.as_slice()).unwrap(), periodic);
+
+        // Repeats closer together than the window can be matched, those
+        // further apart can't; both have to come back intact.
+        for &n in [20000, 40000].iter() {
+            let block = noise(n, n as u64);
+            let mut data = block.clone();
+            data.push_all(block.as_slice());
+            data.push_all(block.as_slice());
+            assert_eq!(round_trip(data.as_slice()), data);
+        }
+    }
+
+    #[test]
+    fn gzip_round_trips() {
+        for data in [vec![], b"vertex 1 0 0\n".to_vec(), noise(5000, 3)].iter() {
+            let gz = gzip(data.as_slice());
+            assert_eq!(gunzip(gz.as_slice()).unwrap(), *data);
+            let n = gz.len();
+            let trailer = |at: usize| (0..4).fold(0u32, |v, k| v | (gz[at + k] as u32) << (8 * k));
+            assert_eq!(trailer(n - 8), crc32(data.as_slice()));
+            assert_eq!(trailer(n - 4) as usize, data.len());
+        }
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(b""), 0);
+    }
+}
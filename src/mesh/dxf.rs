@@ -0,0 +1,24 @@
+use std::old_io::{BufferedWriter,IoResult,Writer};
+use std::old_io::fs::File;
+
+use super::Vector3D;
+
+pub struct DxfFile;
+
+impl DxfFile {
+    // Writes line segments, such as those from Mesh::slice_z, as a minimal
+    // R12 DXF: a header naming the version and one LINE entity per segment
+    // on layer 0. Only X and Y are kept, so the drawing lies flat at Z=0.
+    pub fn write_segments(segments: &[(Vector3D, Vector3D)], path: &str) -> IoResult<()> {
+        let file = try!(File::create(&Path::new(path)));
+        let mut w = BufferedWriter::new(file);
+        try!(w.write_str("0\nSECTION\n2\nHEADER\n9\n$ACADVER\n1\nAC1009\n0\nENDSEC\n"));
+        try!(w.write_str("0\nSECTION\n2\nENTITIES\n"));
+        for &(a, b) in segments.iter() {
+            try!(write!(&mut w, "0\nLINE\n8\n0\n10\n{}\n20\n{}\n30\n0.0\n", a.x, a.y));
+            try!(write!(&mut w, "11\n{}\n21\n{}\n31\n0.0\n", b.x, b.y));
+        }
+        try!(w.write_str("0\nENDSEC\n0\nEOF\n"));
+        w.flush()
+    }
+}
@@ -0,0 +1,153 @@
+use std::old_io::{BufferedWriter,IoResult,Writer};
+use std::old_io::fs::File;
+
+use super::{Mesh, Vector3D};
+
+// GLB container constants from the glTF 2.0 spec.
+const GLB_MAGIC: u32 = 0x46546C67;       // "glTF"
+const GLB_VERSION: u32 = 2;
+const CHUNK_JSON: u32 = 0x4E4F534A;      // "JSON"
+const CHUNK_BIN: u32 = 0x004E4942;       // "BIN\0"
+
+// Component types and buffer view targets, as GL enums.
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+pub struct GltfFile;
+
+impl GltfFile {
+    // Writes a self-contained binary glTF: the 12-byte GLB header, a JSON
+    // chunk describing one mesh with one triangle primitive, then a BIN
+    // chunk holding float32 positions followed by uint32 indices. Chunks
+    // must be 4-byte aligned; the JSON is padded with spaces, and the BIN
+    // chunk is already a multiple of 12 bytes. glTF has no empty accessors
+    // or buffers, so a mesh with no facets gets a scene with one empty node
+    // and no BIN chunk.
+    pub fn write(mesh: &Mesh, path: &str) -> IoResult<()> {
+        let file = try!(File::create(&Path::new(path)));
+        GltfFile::write_to(mesh, &mut BufferedWriter::new(file))
+    }
+
+    fn write_to<W: Writer>(mesh: &Mesh, w: &mut W) -> IoResult<()> {
+        let positions_len = mesh.vertices.len() * 12;
+        let indices_len = mesh.facets.len() * 12;
+        let mut json = GltfFile::json(mesh, positions_len, indices_len).into_bytes();
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+        let bin_len = positions_len + indices_len;
+        let total_len = 12 + 8 + json.len() + if mesh.is_empty() { 0 } else { 8 + bin_len };
+
+        try!(w.write_le_u32(GLB_MAGIC));
+        try!(w.write_le_u32(GLB_VERSION));
+        try!(w.write_le_u32(total_len as u32));
+
+        try!(w.write_le_u32(json.len() as u32));
+        try!(w.write_le_u32(CHUNK_JSON));
+        try!(w.write_all(json.as_slice()));
+        if mesh.is_empty() {
+            return w.flush();
+        }
+
+        try!(w.write_le_u32(bin_len as u32));
+        try!(w.write_le_u32(CHUNK_BIN));
+        for v in mesh.vertices.iter() {
+            try!(w.write_le_f32(v.x));
+            try!(w.write_le_f32(v.y));
+            try!(w.write_le_f32(v.z));
+        }
+        for f in mesh.facets.iter() {
+            try!(w.write_le_u32(f.v1 as u32));
+            try!(w.write_le_u32(f.v2 as u32));
+            try!(w.write_le_u32(f.v3 as u32));
+        }
+        w.flush()
+    }
+
+    // The POSITION accessor is required to carry min and max.
+    fn json(mesh: &Mesh, positions_len: usize, indices_len: usize) -> String {
+        if mesh.is_empty() {
+            return concat!(
+                "{\"asset\":{\"version\":\"2.0\",\"generator\":\"meshman\"},",
+                "\"scene\":0,\"scenes\":[{\"nodes\":[0]}],\"nodes\":[{}]}").to_string();
+        }
+        let zero = Vector3D::new(0.0, 0.0, 0.0);
+        let (min, max) = mesh.bounding_box().unwrap_or((zero, zero));
+        format!(concat!(
+            "{{\"asset\":{{\"version\":\"2.0\",\"generator\":\"meshman\"}},",
+            "\"scene\":0,\"scenes\":[{{\"nodes\":[0]}}],\"nodes\":[{{\"mesh\":0}}],",
+            "\"meshes\":[{{\"primitives\":[{{\"attributes\":{{\"POSITION\":0}},",
+            "\"indices\":1,\"material\":0}}]}}],",
+            "\"materials\":[{{\"pbrMetallicRoughness\":",
+            "{{\"baseColorFactor\":[0.8,0.8,0.8,1.0],\"metallicFactor\":0.0}}}}],",
+            "\"accessors\":[",
+            "{{\"bufferView\":0,\"componentType\":{},\"count\":{},\"type\":\"VEC3\",",
+            "\"min\":[{},{},{}],\"max\":[{},{},{}]}},",
+            "{{\"bufferView\":1,\"componentType\":{},\"count\":{},\"type\":\"SCALAR\"}}],",
+            "\"bufferViews\":[",
+            "{{\"buffer\":0,\"byteOffset\":0,\"byteLength\":{},\"target\":{}}},",
+            "{{\"buffer\":0,\"byteOffset\":{},\"byteLength\":{},\"target\":{}}}],",
+            "\"buffers\":[{{\"byteLength\":{}}}]}}"),
+            FLOAT, mesh.vertices.len(), min.x, min.y, min.z, max.x, max.y, max.z,
+            UNSIGNED_INT, mesh.facets.len() * 3,
+            positions_len, ARRAY_BUFFER,
+            positions_len, indices_len, ELEMENT_ARRAY_BUFFER,
+            positions_len + indices_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::old_io::{MemReader, MemWriter, Reader};
+    use super::{GltfFile, GLB_MAGIC, GLB_VERSION, CHUNK_BIN, CHUNK_JSON};
+    use super::super::Mesh;
+
+    #[test]
+    fn chunk_lengths_add_up() {
+        let cube = Mesh::cube(1.0);
+        let mut w = MemWriter::new();
+        GltfFile::write_to(&cube, &mut w).unwrap();
+        let glb = w.into_inner();
+        let mut r = MemReader::new(glb.clone());
+        assert_eq!(r.read_le_u32().unwrap(), GLB_MAGIC);
+        assert_eq!(r.read_le_u32().unwrap(), GLB_VERSION);
+        assert_eq!(r.read_le_u32().unwrap() as usize, glb.len());
+
+        let json_len = r.read_le_u32().unwrap() as usize;
+        assert_eq!(json_len % 4, 0);
+        assert_eq!(r.read_le_u32().unwrap(), CHUNK_JSON);
+        let json = String::from_utf8(r.read_exact(json_len).unwrap()).unwrap();
+
+        let bin_len = r.read_le_u32().unwrap() as usize;
+        assert_eq!(bin_len, cube.vertex_count() * 12 + cube.facet_count() * 12);
+        assert_eq!(r.read_le_u32().unwrap(), CHUNK_BIN);
+        assert_eq!(r.read_to_end().unwrap().len(), bin_len);
+        assert!(json.contains(format!("\"buffers\":[{{\"byteLength\":{}}}]", bin_len)
+                              .as_slice()));
+        assert!(json.contains(format!("\"byteOffset\":{},\"byteLength\":{}",
+                                      cube.vertex_count() * 12, cube.facet_count() * 12)
+                              .as_slice()));
+    }
+
+    #[test]
+    fn empty_mesh_has_no_buffers() {
+        let mut w = MemWriter::new();
+        GltfFile::write_to(&Mesh::new(), &mut w).unwrap();
+        let glb = w.into_inner();
+        let mut r = MemReader::new(glb.clone());
+        assert_eq!(r.read_le_u32().unwrap(), GLB_MAGIC);
+        assert_eq!(r.read_le_u32().unwrap(), GLB_VERSION);
+        assert_eq!(r.read_le_u32().unwrap() as usize, glb.len());
+
+        let json_len = r.read_le_u32().unwrap() as usize;
+        assert_eq!(json_len % 4, 0);
+        assert_eq!(r.read_le_u32().unwrap(), CHUNK_JSON);
+        let json = String::from_utf8(r.read_exact(json_len).unwrap()).unwrap();
+        assert!(r.read_to_end().unwrap().is_empty());
+        for key in ["\"meshes\"", "\"accessors\"", "\"bufferViews\"", "\"buffers\""].iter() {
+            assert!(!json.contains(*key));
+        }
+    }
+}
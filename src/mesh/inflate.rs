@@ -0,0 +1,404 @@
+// Decoder for raw DEFLATE streams (RFC 1951) and the gzip and ZIP
+// wrappers around them, modelled on zlib's puff.c. It trades speed for
+// brevity, which is fine for the mesh files it is used on.
+
+pub static LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+pub static LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+pub static DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+pub static DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// Order in which code length code lengths are stored in a dynamic block.
+static CLEN_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u32,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    // DEFLATE packs values starting at the least significant bit.
+    fn take(&mut self, n: u32) -> Result<u32, String> {
+        while self.count < n {
+            if self.pos >= self.data.len() {
+                return Err("compressed data ends early".to_string());
+            }
+            self.buf |= (self.data[self.pos] as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let v = self.buf & ((1u32 << n) - 1);
+        self.buf >>= n;
+        self.count -= n;
+        Ok(v)
+    }
+}
+
+// Canonical Huffman code: how many codes there are of each length, and the
+// symbols in code order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &l in lengths.iter() {
+            counts[l as usize] += 1;
+        }
+        let mut offsets = [0u16; 16];
+        for l in 1..15 {
+            offsets[l + 1] = offsets[l] + counts[l];
+        }
+        let mut symbols: Vec<u16> = (0..lengths.len()).map(|_| 0).collect();
+        for (sym, &l) in lengths.iter().enumerate() {
+            if l != 0 {
+                symbols[offsets[l as usize] as usize] = sym as u16;
+                offsets[l as usize] += 1;
+            }
+        }
+        Huffman { counts: counts, symbols: symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, String> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for l in 1..16 {
+            code |= try!(bits.take(1)) as i32;
+            let count = self.counts[l] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("bad Huffman code".to_string())
+    }
+}
+
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut bits = Bits { data: data, pos: 0, buf: 0, count: 0 };
+    let mut out: Vec<u8> = Vec::new();
+    loop {
+        let last = try!(bits.take(1)) == 1;
+        match try!(bits.take(2)) {
+            0 => try!(stored(&mut bits, &mut out)),
+            1 => {
+                let mut lengths = [0u8; 288];
+                for (i, l) in lengths.iter_mut().enumerate() {
+                    *l = if i < 144 { 8 } else if i < 256 { 9 } else if i < 280 { 7 } else { 8 };
+                }
+                let lit = Huffman::new(&lengths);
+                let dist = Huffman::new(&[5u8; 30]);
+                try!(codes(&mut bits, &mut out, &lit, &dist));
+            }
+            2 => {
+                let (lit, dist) = try!(dynamic_tables(&mut bits));
+                try!(codes(&mut bits, &mut out, &lit, &dist));
+            }
+            _ => return Err("bad block type".to_string()),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn stored(bits: &mut Bits, out: &mut Vec<u8>) -> Result<(), String> {
+    // Stored blocks start on a byte boundary.
+    bits.buf = 0;
+    bits.count = 0;
+    if bits.pos + 4 > bits.data.len() {
+        return Err("compressed data ends early".to_string());
+    }
+    let d = bits.data;
+    let len = d[bits.pos] as usize | (d[bits.pos + 1] as usize) << 8;
+    let nlen = d[bits.pos + 2] as usize | (d[bits.pos + 3] as usize) << 8;
+    if len != !nlen & 0xffff {
+        return Err("bad stored block length".to_string());
+    }
+    bits.pos += 4;
+    if bits.pos + len > d.len() {
+        return Err("compressed data ends early".to_string());
+    }
+    out.push_all(&d[bits.pos..bits.pos + len]);
+    bits.pos += len;
+    Ok(())
+}
+
+fn dynamic_tables(bits: &mut Bits) -> Result<(Huffman, Huffman), String> {
+    let nlit = try!(bits.take(5)) as usize + 257;
+    let ndist = try!(bits.take(5)) as usize + 1;
+    let nclen = try!(bits.take(4)) as usize + 4;
+    let mut clens = [0u8; 19];
+    for i in 0..nclen {
+        clens[CLEN_ORDER[i]] = try!(bits.take(3)) as u8;
+    }
+    let clen = Huffman::new(&clens);
+    let mut lengths: Vec<u8> = Vec::with_capacity(nlit + ndist);
+    while lengths.len() < nlit + ndist {
+        let sym = try!(clen.decode(bits));
+        let (value, repeat) = match sym {
+            0...15 => (sym as u8, 1),
+            16 => match lengths.last() {
+                Some(&prev) => (prev, 3 + try!(bits.take(2)) as usize),
+                None => return Err("repeat with no previous length".to_string()),
+            },
+            17 => (0, 3 + try!(bits.take(3)) as usize),
+            _ => (0, 11 + try!(bits.take(7)) as usize),
+        };
+        if lengths.len() + repeat > nlit + ndist {
+            return Err("too many code lengths".to_string());
+        }
+        for _ in 0..repeat {
+            lengths.push(value);
+        }
+    }
+    Ok((Huffman::new(&lengths[..nlit]), Huffman::new(&lengths[nlit..])))
+}
+
+fn codes(bits: &mut Bits, out: &mut Vec<u8>, lit: &Huffman, dist: &Huffman)
+        -> Result<(), String> {
+    loop {
+        let sym = try!(lit.decode(bits)) as usize;
+        if sym < 256 {
+            out.push(sym as u8);
+        } else if sym == 256 {
+            return Ok(());
+        } else {
+            let li = sym - 257;
+            if li >= LENGTH_BASE.len() {
+                return Err("bad length code".to_string());
+            }
+            let len = LENGTH_BASE[li] as usize + try!(bits.take(LENGTH_EXTRA[li] as u32)) as usize;
+            let di = try!(dist.decode(bits)) as usize;
+            if di >= DIST_BASE.len() {
+                return Err("bad distance code".to_string());
+            }
+            let d = DIST_BASE[di] as usize + try!(bits.take(DIST_EXTRA[di] as u32)) as usize;
+            if d > out.len() {
+                return Err("distance too far back".to_string());
+            }
+            let start = out.len() - d;
+            for i in 0..len {
+                let b = out[start + i];
+                out.push(b);
+            }
+        }
+    }
+}
+
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.len() >= 2 && data[0] == 0x1f && data[1] == 0x8b
+}
+
+// Unwraps a single-member gzip file (RFC 1952), skipping the optional
+// extra, name, comment and header CRC fields.
+pub fn gunzip(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < 18 || !is_gzip(data) || data[2] != 8 {
+        return Err("not a deflate gzip file".to_string());
+    }
+    let flags = data[3];
+    let mut pos = 10;
+    if flags & 4 != 0 {
+        let xlen = data[pos] as usize | (data[pos + 1] as usize) << 8;
+        pos += 2 + xlen;
+    }
+    for &flag in [8u8, 16].iter() {
+        if flags & flag != 0 {
+            while pos < data.len() && data[pos] != 0 {
+                pos += 1;
+            }
+            pos += 1;
+        }
+    }
+    if flags & 2 != 0 {
+        pos += 2;
+    }
+    if pos > data.len() {
+        return Err("truncated gzip header".to_string());
+    }
+    inflate(&data[pos..])
+}
+
+pub fn is_zip(data: &[u8]) -> bool {
+    data.len() >= 4 && data[0] == b'P' && data[1] == b'K' && data[2] == 3 && data[3] == 4
+}
+
+// Extracts the first entry of a ZIP archive, which is where single-file
+// formats like compressed AMF keep their content.
+pub fn unzip_first(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < 30 || !is_zip(data) {
+        return Err("not a ZIP file".to_string());
+    }
+    let u16_at = |i: usize| data[i] as usize | (data[i + 1] as usize) << 8;
+    let flags = u16_at(6);
+    let method = u16_at(8);
+    let size = u16_at(18) | u16_at(20) << 16;
+    let start = 30 + u16_at(26) + u16_at(28);
+    if start > data.len() {
+        return Err("truncated ZIP header".to_string());
+    }
+    // With a data descriptor the size isn't known up front, but inflate
+    // finds the end of the stream by itself.
+    let end = if flags & 8 != 0 { data.len() } else { start + size };
+    if end > data.len() {
+        return Err("truncated ZIP entry".to_string());
+    }
+    match method {
+        0 => Ok(data[start..end].to_vec()),
+        8 => inflate(&data[start..end]),
+        m => Err(format!("unsupported ZIP compression method {}", m)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{gunzip, inflate, unzip_first};
+
+    // `gzip -9n` of the bytes 0xf0 to 0xff, which it keeps in a stored
+    // block since they don't compress.
+    static STORED_GZ: [u8; 39] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x01, 0x10,
+        0x00, 0xef, 0xff, 0xf0, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+        0xf9, 0xfa, 0xfb, 0xfc, 0xfd, 0xfe, 0xff, 0x3c, 0x44, 0xe8, 0x61, 0x10,
+        0x00, 0x00, 0x00];
+
+    // `gzip -9` of vertices.txt, three lines of "vertex 1 0 0": a fixed
+    // Huffman block behind a header with the file name in it.
+    static FIXED_GZ: [u8; 49] = [
+        0x1f, 0x8b, 0x08, 0x08, 0xc0, 0x21, 0xe7, 0x54, 0x02, 0x03, 0x76, 0x65,
+        0x72, 0x74, 0x69, 0x63, 0x65, 0x73, 0x2e, 0x74, 0x78, 0x74, 0x00, 0x2b,
+        0x4b, 0x2d, 0x2a, 0x49, 0xad, 0x50, 0x30, 0x54, 0x30, 0x50, 0x30, 0xe0,
+        0x2a, 0xc3, 0xc9, 0x01, 0x00, 0x4c, 0xe7, 0xc6, 0x5e, 0x27, 0x00, 0x00,
+        0x00];
+
+    // `gzip -9n` of the facets from dynamic_text(), which is long and
+    // repetitive enough to get a dynamic Huffman block.
+    static DYNAMIC_GZ: [u8; 163] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xa5, 0x93,
+        0x41, 0x0a, 0x83, 0x30, 0x10, 0x45, 0xf7, 0x9e, 0xe2, 0x1f, 0xa1, 0x13,
+        0xb5, 0xb5, 0xc7, 0x11, 0x3b, 0x59, 0x59, 0x23, 0x21, 0x2d, 0x3d, 0xbe,
+        0x51, 0xc4, 0x45, 0xc6, 0x81, 0xe2, 0x90, 0x45, 0xc8, 0x24, 0x3c, 0xfe,
+        0xe4, 0x31, 0xbe, 0x1f, 0x38, 0x61, 0x0a, 0xf1, 0xdd, 0x8f, 0xb8, 0xad,
+        0xab, 0x02, 0xc2, 0x27, 0x71, 0xc4, 0x18, 0xc2, 0x9c, 0x0f, 0xc0, 0x97,
+        0x63, 0xe2, 0xdf, 0x71, 0x7b, 0x14, 0x68, 0x2f, 0xf0, 0xf4, 0xda, 0xde,
+        0xe6, 0xdd, 0xaf, 0xb8, 0xca, 0x97, 0x50, 0x52, 0xa1, 0x04, 0x57, 0x42,
+        0xe9, 0x4f, 0xa8, 0x9e, 0xd4, 0xa1, 0x29, 0xa1, 0xce, 0x9c, 0xb4, 0xde,
+        0x83, 0x5d, 0x68, 0x5f, 0x4f, 0xda, 0x64, 0xec, 0xc5, 0xf6, 0xf5, 0xa4,
+        0xad, 0x14, 0xe5, 0xcc, 0x49, 0xef, 0x52, 0x94, 0xdd, 0xfe, 0x43, 0x8a,
+        0xb2, 0xdb, 0xef, 0xa4, 0x28, 0xbb, 0xfd, 0xa7, 0x14, 0x65, 0xb7, 0x4f,
+        0x27, 0x23, 0x65, 0xd7, 0x4f, 0x27, 0x33, 0xa5, 0x7c, 0xc0, 0x02, 0x11,
+        0xea, 0x14, 0x67, 0xfe, 0x03, 0x00, 0x00];
+
+    // `zip - -` reading "solid cube\nendsolid cube\n" from a pipe, so the
+    // sizes come after the data in a descriptor.
+    static DESCRIPTOR_ZIP: [u8; 162] = [
+        0x50, 0x4b, 0x03, 0x04, 0x2d, 0x00, 0x08, 0x00, 0x08, 0x00, 0x17, 0x1d,
+        0x4f, 0x5d, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0x01, 0x00, 0x14, 0x00, 0x2d, 0x01, 0x00, 0x10, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x2b, 0xce, 0xcf, 0xc9, 0x4c, 0x51, 0x48, 0x2e, 0x4d,
+        0x4a, 0xe5, 0x4a, 0xcd, 0x4b, 0x29, 0x46, 0xf0, 0x00, 0x50, 0x4b, 0x07,
+        0x08, 0x16, 0xe5, 0x39, 0xfe, 0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x19, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x50, 0x4b, 0x01,
+        0x02, 0x1e, 0x03, 0x2d, 0x00, 0x08, 0x00, 0x08, 0x00, 0x17, 0x1d, 0x4f,
+        0x5d, 0x16, 0xe5, 0x39, 0xfe, 0x12, 0x00, 0x00, 0x00, 0x19, 0x00, 0x00,
+        0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+        0x00, 0x80, 0x11, 0x00, 0x00, 0x00, 0x00, 0x2d, 0x50, 0x4b, 0x05, 0x06,
+        0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x2f, 0x00, 0x00, 0x00,
+        0x5d, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+    fn dynamic_text() -> String {
+        let mut s = String::new();
+        for i in 0..12 {
+            s.push_str(format!("facet normal 0 0 {}\n  outer loop\n    vertex {} {} 0\n    \
+                                vertex 1 {} 0\n  endloop\nendfacet\n",
+                               i % 2, i, i * 7 % 5, i % 3).as_slice());
+        }
+        s
+    }
+
+    // Block type from the first deflate byte after a gzip header of `len`.
+    fn block_type(gz: &[u8], len: usize) -> u8 {
+        (gz[len] >> 1) & 3
+    }
+
+    #[test]
+    fn stored_block() {
+        assert_eq!(block_type(&STORED_GZ, 10), 0);
+        let expected: Vec<u8> = (0xf0..0x100).map(|b: u32| b as u8).collect();
+        assert_eq!(gunzip(&STORED_GZ).unwrap(), expected);
+    }
+
+    #[test]
+    fn fixed_block() {
+        assert_eq!(block_type(&FIXED_GZ, 23), 1);
+        assert_eq!(gunzip(&FIXED_GZ).unwrap(),
+                   b"vertex 1 0 0\nvertex 1 0 0\nvertex 1 0 0\n".to_vec());
+    }
+
+    #[test]
+    fn dynamic_block() {
+        assert_eq!(block_type(&DYNAMIC_GZ, 10), 2);
+        assert_eq!(gunzip(&DYNAMIC_GZ).unwrap(), dynamic_text().into_bytes());
+    }
+
+    #[test]
+    fn zip_entry_with_data_descriptor() {
+        assert_eq!(unzip_first(&DESCRIPTOR_ZIP).unwrap(),
+                   b"solid cube\nendsolid cube\n".to_vec());
+    }
+
+    #[test]
+    fn truncated_input_is_an_error() {
+        // Anything cut short of the end of the deflate stream; gunzip
+        // doesn't need the 8 byte trailer.
+        for &gz in [&STORED_GZ[..], &FIXED_GZ[..], &DYNAMIC_GZ[..]].iter() {
+            for cut in 0..gz.len() - 8 {
+                assert!(gunzip(&gz[..cut]).is_err(), "cut at {}", cut);
+            }
+        }
+        // Local header, name and extra field are 51 bytes, then 18 of data.
+        for cut in 0..51 + 18 {
+            assert!(unzip_first(&DESCRIPTOR_ZIP[..cut]).is_err(), "cut at {}", cut);
+        }
+    }
+
+    #[test]
+    fn corrupt_input_is_an_error() {
+        // Block type 3 is reserved.
+        assert!(inflate(&[0x07]).is_err());
+        // Stored block whose length doesn't match its complement.
+        assert!(inflate(&[0x01, 0x02, 0x00, 0x00, 0x00, 0xaa, 0xbb]).is_err());
+        // Fixed block opening with a match, which has nothing to copy from.
+        assert!(inflate(&[0x03, 0x02, 0x00]).is_err());
+        assert!(gunzip(b"solid cube\nendsolid cube\n").is_err());
+        assert!(unzip_first(b"solid cube\nendsolid cube\n").is_err());
+    }
+
+    #[test]
+    fn damaged_input_does_not_panic() {
+        for i in 0..DYNAMIC_GZ.len() {
+            let mut gz = DYNAMIC_GZ.to_vec();
+            gz[i] ^= 0x55;
+            let _ = gunzip(gz.as_slice());
+        }
+        for i in 0..DESCRIPTOR_ZIP.len() {
+            let mut zip = DESCRIPTOR_ZIP.to_vec();
+            zip[i] ^= 0x55;
+            let _ = unzip_first(zip.as_slice());
+        }
+    }
+}
@@ -0,0 +1,80 @@
+// A k-d tree over a mesh's vertices for finding the one nearest a point,
+// as picking and snapping need. It is only built when asked for, by
+// Mesh::build_kdtree, so reading a mesh doesn't pay for it.
+
+use std::cmp::Ordering;
+
+use super::Vector3D;
+
+pub struct KdTree<'a> {
+    points: &'a [Vector3D],
+    // Vertex indices laid out as an implicit tree: the middle entry of any
+    // range is a node splitting the entries before and after it on the
+    // axis for its depth, cycling through x, y and z.
+    order: Vec<usize>,
+}
+
+impl<'a> KdTree<'a> {
+    pub fn new(points: &'a [Vector3D]) -> KdTree<'a> {
+        let mut order: Vec<usize> = (0..points.len()).collect();
+        build(points, order.as_mut_slice(), 0);
+        KdTree { points: points, order: order }
+    }
+
+    // Index of the vertex nearest p, or None if there are no vertices.
+    // Ties go to whichever is found first.
+    pub fn nearest(&self, p: Vector3D) -> Option<usize> {
+        let mut best: Option<(usize, f32)> = None;
+        self.search(&p, 0, self.order.len(), 0, &mut best);
+        best.map(|(i, _)| i)
+    }
+
+    // Visits the side of each node p is on first, then the other side
+    // only if the splitting plane is closer than the best match so far.
+    fn search(&self, p: &Vector3D, lo: usize, hi: usize, depth: usize,
+              best: &mut Option<(usize, f32)>) {
+        if lo >= hi {
+            return;
+        }
+        let mid = lo + (hi - lo) / 2;
+        let i = self.order[mid];
+        let q = self.points[i];
+        let d = q - *p;
+        let dist = d.dot(&d);
+        if best.map_or(true, |(_, b)| dist < b) {
+            *best = Some((i, dist));
+        }
+        let diff = coord(p, depth) - coord(&q, depth);
+        let (near, far) = if diff < 0.0 {
+            ((lo, mid), (mid + 1, hi))
+        } else {
+            ((mid + 1, hi), (lo, mid))
+        };
+        self.search(p, near.0, near.1, depth + 1, best);
+        if best.map_or(true, |(_, b)| diff * diff < b) {
+            self.search(p, far.0, far.1, depth + 1, best);
+        }
+    }
+}
+
+fn coord(v: &Vector3D, depth: usize) -> f32 {
+    match depth % 3 {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+// Sorts the range on the axis for its depth so its middle entry splits it,
+// then does the same for each half.
+fn build(points: &[Vector3D], order: &mut [usize], depth: usize) {
+    if order.len() <= 1 {
+        return;
+    }
+    order.sort_by(|&a, &b| {
+        coord(&points[a], depth).partial_cmp(&coord(&points[b], depth)).unwrap_or(Ordering::Equal)
+    });
+    let mid = order.len() / 2;
+    build(points, &mut order[..mid], depth + 1);
+    build(points, &mut order[mid + 1..], depth + 1);
+}
@@ -2,15 +2,98 @@
 //mod mesh;
 
 use std::cmp::*;
-use std::collections::HashMap;
-use std::f32::NAN;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
-use std::old_io::{BufferedReader,Reader};
+use std::old_io::{Buffer,BufferedReader,BufferedWriter,IoResult,MemReader,MemWriter,Reader,Writer};
+use std::old_io::fs::File;
+use std::sync::atomic::{AtomicBool, AtomicUsize, ATOMIC_BOOL_INIT, ATOMIC_USIZE_INIT, Ordering};
+use std::thread;
 use std::hash::{Hash, Hasher};
 use std::mem::{transmute};
+use std::num::Float;
+use std::ops::{Add, Mul, Neg, Sub};
+use std::slice;
 use std::vec::Vec;
 
-#[derive(PartialEq, PartialOrd, Copy)] //Show,
+pub use amf::AmfFile;
+pub use dxf::DxfFile;
+pub use gltf::GltfFile;
+pub use kdtree::KdTree;
+pub use matrix::Matrix4;
+pub use obj::ObjFile;
+pub use off::OffFile;
+pub use ply::PlyFile;
+pub use pov::PovFile;
+pub use stats::stats_json;
+pub use svg::SvgFile;
+pub use threemf::ThreeMfFile;
+
+// Diagnostics go to stderr so they never end up mixed into mesh data that
+// is being written to stdout. They are progress chatter and only print when
+// verbose output has been switched on; problems are returned as MeshError.
+macro_rules! diag {
+    ($($arg:tt)*) => ({
+        if ::verbose() {
+            let _ = writeln!(&mut ::std::old_io::stderr(), $($arg)*);
+        }
+    })
+}
+
+mod amf;
+mod deflate;
+mod dxf;
+mod gltf;
+mod inflate;
+mod kdtree;
+mod matrix;
+mod obj;
+mod off;
+mod ply;
+mod pov;
+mod stats;
+mod svg;
+mod threemf;
+
+static VERBOSE: AtomicBool = ATOMIC_BOOL_INIT;
+
+// Turns on the readers' diagnostic output (header, format and counts). The
+// library is quiet by default.
+pub fn set_verbose(on: bool) {
+    VERBOSE.store(on, Ordering::Relaxed);
+}
+
+pub fn verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+static PROGRESS: AtomicBool = ATOMIC_BOOL_INIT;
+
+// Turns on a percentage counter on stderr while binary STL is read.
+pub fn set_progress(on: bool) {
+    PROGRESS.store(on, Ordering::Relaxed);
+}
+
+pub fn progress() -> bool {
+    PROGRESS.load(Ordering::Relaxed)
+}
+
+static THREADS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// Below this many vertices map_vertices stays on the calling thread, since
+// spawning would cost more than it saves.
+const PARALLEL_MIN_VERTICES: usize = 100000;
+
+// Sets how many threads per-vertex transforms may use. The default of one
+// keeps everything on the calling thread.
+pub fn set_threads(n: usize) {
+    THREADS.store(n, Ordering::Relaxed);
+}
+
+pub fn threads() -> usize {
+    max(THREADS.load(Ordering::Relaxed), 1)
+}
+
+#[derive(PartialEq, PartialOrd, Copy, Clone)] //Show,
 pub struct Vector3D {
     x: f32,
     y: f32,
@@ -31,15 +114,68 @@ impl fmt::Debug for Vector3D {
 }
 
 impl Vector3D {
-    fn read(r: &mut Reader) -> Vector3D {
-        let xr = r.read_le_f32();
-        let yr = r.read_le_f32();
-        let zr = r.read_le_f32();
-        if xr.is_ok() && yr.is_ok() && zr.is_ok() {
-            let v = Vector3D { x: xr.ok().unwrap(), y: yr.ok().unwrap(), z: zr.ok().unwrap() };
-            return v
+    pub fn new(x: f32, y: f32, z: f32) -> Vector3D {
+        Vector3D { x: x, y: y, z: z }
+    }
+
+    pub fn x(&self) -> f32 {
+        self.x
+    }
+
+    pub fn y(&self) -> f32 {
+        self.y
+    }
+
+    pub fn z(&self) -> f32 {
+        self.z
+    }
+
+    pub fn dot(&self, other: &Vector3D) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(&self, other: &Vector3D) -> Vector3D {
+        Vector3D {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
+
+    pub fn length(&self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    // Whether every coordinate is within eps of other's.
+    pub fn near(&self, other: &Vector3D, eps: f32) -> bool {
+        (self.x - other.x).abs() <= eps && (self.y - other.y).abs() <= eps
+            && (self.z - other.z).abs() <= eps
+    }
+
+    // Whether no coordinate is NaN or infinite.
+    pub fn is_finite(&self) -> bool {
+        self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
+    }
+
+    // Unit vector in the same direction. A zero vector has no direction and
+    // is returned as is rather than turning into NaNs.
+    pub fn normalize(&self) -> Vector3D {
+        let len = self.length();
+        if len == 0.0 {
+            return *self;
         }
-        return Vector3D { x: NAN, y: NAN, z: NAN };
+        Vector3D { x: self.x / len, y: self.y / len, z: self.z / len }
+    }
+
+    fn read<R: Reader>(r: &mut R, order: ByteOrder) -> IoResult<Vector3D> {
+        let mut c = [0.0f32; 3];
+        for v in c.iter_mut() {
+            *v = try!(match order {
+                ByteOrder::Little => r.read_le_f32(),
+                ByteOrder::Big => r.read_be_f32(),
+            });
+        }
+        Ok(Vector3D { x: c[0], y: c[1], z: c[2] })
     }
 }
 
@@ -59,6 +195,38 @@ impl Hash for Vector3D {
     }
 }
 
+impl Add for Vector3D {
+    type Output = Vector3D;
+
+    fn add(self, other: Vector3D) -> Vector3D {
+        Vector3D { x: self.x + other.x, y: self.y + other.y, z: self.z + other.z }
+    }
+}
+
+impl Sub for Vector3D {
+    type Output = Vector3D;
+
+    fn sub(self, other: Vector3D) -> Vector3D {
+        Vector3D { x: self.x - other.x, y: self.y - other.y, z: self.z - other.z }
+    }
+}
+
+impl Mul<f32> for Vector3D {
+    type Output = Vector3D;
+
+    fn mul(self, k: f32) -> Vector3D {
+        Vector3D { x: self.x * k, y: self.y * k, z: self.z * k }
+    }
+}
+
+impl Neg for Vector3D {
+    type Output = Vector3D;
+
+    fn neg(self) -> Vector3D {
+        Vector3D { x: -self.x, y: -self.y, z: -self.z }
+    }
+}
+
 #[derive(PartialEq, Eq, Hash, Copy)]
 pub struct StlFacet {
     n : Vector3D,
@@ -76,18 +244,28 @@ impl fmt::Debug for StlFacet {
 }
 
 impl StlFacet {
-    fn read(r: &mut Reader) -> StlFacet {
-        let n = Vector3D::read(r);
-        let v1 = Vector3D::read(r);
-        let v2 = Vector3D::read(r);
-        let v3 = Vector3D::read(r);
-        let abc = r.read_le_u16().unwrap();
-        StlFacet { n:n, v1:v1, v2:v2, v3:v3, abc:abc }
+    fn read<R: Reader>(r: &mut R, order: ByteOrder) -> IoResult<StlFacet> {
+        let n = try!(Vector3D::read(r, order));
+        let v1 = try!(Vector3D::read(r, order));
+        let v2 = try!(Vector3D::read(r, order));
+        let v3 = try!(Vector3D::read(r, order));
+        let abc = try!(match order {
+            ByteOrder::Little => r.read_le_u16(),
+            ByteOrder::Big => r.read_be_u16(),
+        });
+        Ok(StlFacet { n:n, v1:v1, v2:v2, v3:v3, abc:abc })
     }
 }
 
+// Vertices are keyed on the bits of their coordinates rather than compared
+// as floats, so that a NaN, which never equals itself, still finds the entry
+// add made for it and a corrupt file can be read and then validated.
 pub struct VertexMap {
-    vertices: HashMap<Vector3D,usize>,
+    vertices: HashMap<(u32, u32, u32), usize>,
+}
+
+fn vertex_bits(v: &Vector3D) -> (u32, u32, u32) {
+    unsafe { (transmute(v.x), transmute(v.y), transmute(v.z)) }
 }
 
 impl VertexMap {
@@ -97,52 +275,431 @@ impl VertexMap {
         }
     }
 
+    pub fn with_capacity(n: usize) -> VertexMap {
+        VertexMap {
+            vertices: HashMap::with_capacity(n),
+        }
+    }
+
     pub fn len(&self) -> usize {
         self.vertices.len()
     }
 
     pub fn get(&self, v3d: &Vector3D) -> usize {
-        *self.vertices.get(v3d).unwrap()
+        *self.vertices.get(&vertex_bits(v3d)).unwrap()
     }
 
     pub fn add(&mut self, vertex: Vector3D) -> usize {
-        if self.vertices.contains_key(&vertex) {
-            //println!("add existing {}", vector3d_str(&vertex));
-            *self.vertices.get(&vertex).unwrap()
-        } else {
-            //println!("add new {}", vector3d_str(&vertex));
-            let idx = self.vertices.len();
-            self.vertices.insert(vertex, idx);
-            idx
+        let key = vertex_bits(&vertex);
+        if let Some(&idx) = self.vertices.get(&key) {
+            return idx;
         }
+        let idx = self.vertices.len();
+        self.vertices.insert(key, idx);
+        idx
     }
 
+    // The vertices ordered by the index add gave them.
     pub fn vector(&self) -> Vec<Vector3D> {
-        let mut v: Vec<Vector3D> = Vec::with_capacity(self.vertices.len());
-        for (v3d, idx) in self.vertices.iter() {
-            v.push(*v3d);  // Funkiness just to get v expanded. Below writes the real values.
+        let zero = Vector3D { x: 0.0, y: 0.0, z: 0.0 };
+        let mut v: Vec<Vector3D> = (0..self.vertices.len()).map(|_| zero).collect();
+        for (&(x, y, z), idx) in self.vertices.iter() {
+            v[*idx] = unsafe { Vector3D { x: transmute(x), y: transmute(y), z: transmute(z) } };
         }
-        for (v3d, idx) in self.vertices.iter() {
-            //println!("v3d:{:?} idx:{}", v3d, idx);
-            v[*idx] = *v3d;
-        }
-        //for i in 0..v.len() {
-        //    println!("v3d[{}]: {:?}", i, v[i]);
-        //}
         v
     }
 }
 
-#[derive(PartialEq, Debug, Eq, Hash, Copy)]
+// abc is the binary STL attribute word, carried through so it can be
+// written back out. Many tools keep a facet color there; 0 means none.
+// material is the material the facet is printed in, for formats that have
+// them; 0 is the default material. normal is the normal an STL file gave
+// the facet, kept for checking against its winding and written back out.
+// Rigid and affine transforms carry it along; operations that reshape
+// facets in other ways drop it.
+#[derive(PartialEq, Debug, Eq, Hash, Copy, Clone)]
 pub struct Facet {
     v1: usize,
     v2: usize,
     v3: usize,
+    abc: u16,
+    material: u32,
+    normal: Option<Vector3D>,
+}
+
+impl Facet {
+    pub fn new(v1: usize, v2: usize, v3: usize) -> Facet {
+        Facet { v1: v1, v2: v2, v3: v3, abc: 0, material: 0, normal: None }
+    }
+
+    pub fn v1(&self) -> usize {
+        self.v1
+    }
+
+    pub fn v2(&self) -> usize {
+        self.v2
+    }
+
+    pub fn v3(&self) -> usize {
+        self.v3
+    }
+
+    pub fn abc(&self) -> u16 {
+        self.abc
+    }
+
+    // The color kept in abc the way VisCAM and SolidView write it: bit 15
+    // set when there is one, then 5 bits each of red (bits 10-14), green
+    // (5-9) and blue (0-4), scaled here to 8 bits.
+    pub fn color(&self) -> Option<Color> {
+        if self.abc & 0x8000 == 0 {
+            return None;
+        }
+        let level = |shift: usize| (((self.abc >> shift) & 31) as u32 * 255 / 31) as u8;
+        Some(Color { r: level(10), g: level(5), b: level(0) })
+    }
+
+    pub fn material(&self) -> u32 {
+        self.material
+    }
+
+    // The same facet wound the other way round, with its stored normal
+    // negated to match.
+    fn reversed(&self) -> Facet {
+        Facet { v2: self.v3, v3: self.v2, normal: self.normal.map(|n| -n), ..*self }
+    }
+
+    pub fn stored_normal(&self) -> Option<Vector3D> {
+        self.normal
+    }
+}
+
+// Line-at-a-time state machine for the facet/outer loop/vertex structure of
+// ASCII STL. Facets are collected and their vertices deduplicated through a
+// VertexMap just as read_binary does.
+struct AsciiStlParser {
+    solids: Vec<Mesh>,
+    name: Option<String>,
+    facets: Vec<StlFacet>,
+    vertices: VertexMap,
+    normal: Option<Vector3D>,
+    loop_vertices: Vec<Vector3D>,
+    in_loop: bool,
+    line_no: usize,
+    done: bool,
+}
+
+impl AsciiStlParser {
+    fn new() -> AsciiStlParser {
+        AsciiStlParser {
+            solids: Vec::new(),
+            name: None,
+            facets: Vec::new(),
+            vertices: VertexMap::new(),
+            normal: None,
+            loop_vertices: Vec::with_capacity(3),
+            in_loop: false,
+            line_no: 0,
+            done: false,
+        }
+    }
+
+    fn parse_vector(&self, tokens: &[&str]) -> Result<Vector3D, String> {
+        if tokens.len() != 3 {
+            return Err(format!("line {}: expected 3 coordinates, found {}",
+                self.line_no, tokens.len()));
+        }
+        let mut c = [0.0f32; 3];
+        for (i, t) in tokens.iter().enumerate() {
+            c[i] = match t.parse::<f32>() {
+                Ok(f) => f,
+                Err(_) => return Err(format!("line {}: bad number '{}'", self.line_no, t)),
+            };
+        }
+        Ok(Vector3D { x: c[0], y: c[1], z: c[2] })
+    }
+
+    // After endsolid anything but the start of another solid is ignored,
+    // as some exporters leave junk at the end of the file.
+    fn line(&mut self, line: &str) -> Result<(), String> {
+        self.line_no += 1;
+        let tokens: Vec<&str> = line.words().collect();
+        if tokens.is_empty() || (self.done && tokens[0] != "solid") {
+            return Ok(());
+        }
+        match tokens[0] {
+            "solid" => {
+                if self.normal.is_some() {
+                    return Err(format!("line {}: solid inside a facet", self.line_no));
+                }
+                self.end_solid();
+                self.done = false;
+                self.name = if tokens.len() > 1 { Some(tokens[1..].connect(" ")) } else { None };
+            }
+            "facet" => {
+                if self.normal.is_some() {
+                    return Err(format!("line {}: facet without endfacet", self.line_no));
+                }
+                if tokens.len() < 2 || tokens[1] != "normal" {
+                    return Err(format!("line {}: expected 'facet normal'", self.line_no));
+                }
+                self.normal = Some(try!(self.parse_vector(&tokens[2..])));
+            }
+            "outer" => {
+                if self.normal.is_none() || self.in_loop {
+                    return Err(format!("line {}: 'outer loop' outside a facet", self.line_no));
+                }
+                self.in_loop = true;
+            }
+            "vertex" => {
+                if !self.in_loop {
+                    return Err(format!("line {}: vertex outside a loop", self.line_no));
+                }
+                let v = try!(self.parse_vector(&tokens[1..]));
+                self.loop_vertices.push(v);
+            }
+            "endloop" => {
+                if self.loop_vertices.len() != 3 {
+                    return Err(format!("line {}: loop has {} vertices, expected 3",
+                        self.line_no, self.loop_vertices.len()));
+                }
+                self.in_loop = false;
+            }
+            "endfacet" => {
+                let n = match self.normal {
+                    Some(n) if !self.in_loop && self.loop_vertices.len() == 3 => n,
+                    _ => return Err(format!("line {}: incomplete facet", self.line_no)),
+                };
+                let f = StlFacet {
+                    n: n,
+                    v1: self.loop_vertices[0],
+                    v2: self.loop_vertices[1],
+                    v3: self.loop_vertices[2],
+                    abc: 0,
+                };
+                self.vertices.add(f.v1);
+                self.vertices.add(f.v2);
+                self.vertices.add(f.v3);
+                self.facets.push(f);
+                self.normal = None;
+                self.loop_vertices.clear();
+            }
+            "endsolid" => {
+                if self.normal.is_some() {
+                    return Err(format!("line {}: endsolid inside a facet", self.line_no));
+                }
+                self.end_solid();
+                self.done = true;
+            }
+            t => return Err(format!("line {}: unexpected '{}'", self.line_no, t)),
+        }
+        Ok(())
+    }
+
+    // Feeds header, the text already read while sniffing the format, and
+    // then the rest of r through line.
+    fn read<R: Reader>(&mut self, header: &str, r: &mut BufferedReader<R>)
+            -> Result<(), String> {
+        let mut head_lines: Vec<&str> = header.split('\n').collect();
+        let mut partial = head_lines.pop().unwrap_or("").to_string();
+        for line in head_lines.iter() {
+            try!(self.line(line));
+        }
+        for line in r.lines() {
+            let line = match line {
+                Ok(l) => l,
+                // Junk after the last endsolid needn't even be text.
+                Err(_) if self.done => break,
+                Err(e) => return Err(format!("read error: {}", e)),
+            };
+            if partial.is_empty() {
+                try!(self.line(line.as_slice()));
+            } else {
+                partial.push_str(line.as_slice());
+                try!(self.line(partial.as_slice()));
+                partial.clear();
+            }
+        }
+        if !partial.is_empty() {
+            try!(self.line(partial.as_slice()));
+        }
+        Ok(())
+    }
+
+    // Wraps up the facets read since the last solid line as a mesh of
+    // their own. Solids with no facets are dropped.
+    fn end_solid(&mut self) {
+        if self.facets.is_empty() {
+            return;
+        }
+        diag!("Solid {}: {} facets, {} vertices", self.name.as_ref().map_or("", |n| n.as_slice()),
+            self.facets.len(), self.vertices.len());
+        let mut mesh = Mesh::new_from_stl(&self.facets, &self.vertices);
+        mesh.name = self.name.take();
+        self.solids.push(mesh);
+        self.facets.clear();
+        self.vertices = VertexMap::new();
+    }
+
+    // Drops the facet being read, if any, so that a recovering read keeps
+    // the facets before the damage, and says what was lost.
+    fn abandon(&mut self, why: String) -> Shortfall {
+        let kept = self.solids.iter().fold(self.facets.len(), |n, m| n + m.facets.len());
+        self.normal = None;
+        self.loop_vertices.clear();
+        self.in_loop = false;
+        self.done = true;
+        Shortfall { reason: MeshError::Malformed(why), kept: kept, declared: None }
+    }
+
+    fn finish(mut self, recover: bool) -> Result<(Vec<Mesh>, Option<Shortfall>), String> {
+        let mut shortfall = None;
+        if self.normal.is_some() {
+            let why = format!("line {}: file ends inside a facet", self.line_no);
+            if !recover {
+                return Err(why);
+            }
+            shortfall = Some(self.abandon(why));
+        }
+        self.end_solid();
+        Ok((self.solids, shortfall))
+    }
+}
+
+// Why a mesh file could not be read.
+#[derive(PartialEq, Debug, Clone)]
+pub enum MeshError {
+    // Shorter than the 80-byte STL header.
+    TruncatedHeader,
+    // Binary STL ending before the u32 facet count.
+    TruncatedFacetCount,
+    // Binary STL ending partway through the facet with this index.
+    TruncatedFacetData(usize),
+    // Text formats (ASCII STL, OBJ) that don't parse, with the reason.
+    Malformed(String),
+    // The file couldn't be opened, with the reason.
+    Io(String),
+}
+
+// What a recovering read (see Mesh::read_solids_recover) had to leave out
+// of a damaged file: the error it stopped at, how many facets it kept and,
+// for binary STL, how many the file said it had.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Shortfall {
+    pub reason: MeshError,
+    pub kept: usize,
+    pub declared: Option<usize>,
+}
+
+impl fmt::Display for Shortfall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.declared {
+            Some(n) => write!(f, "{}, only {} of {} facets could be read",
+                              self.reason, self.kept, n),
+            None => write!(f, "{}, keeping the {} facets before it", self.reason, self.kept),
+        }
+    }
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MeshError::TruncatedHeader => write!(f, "truncated STL header"),
+            MeshError::TruncatedFacetCount => write!(f, "truncated STL facet count"),
+            MeshError::TruncatedFacetData(i) => write!(f, "truncated STL data in facet {}", i),
+            MeshError::Malformed(ref e) => write!(f, "malformed mesh: {}", e),
+            MeshError::Io(ref e) => write!(f, "file error: {}", e),
+        }
+    }
+}
+
+// An 8-bit RGB color, as scanners attach to each vertex.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
 }
 
 pub struct Mesh {
     vertices: Vec<Vector3D>,
     facets: Vec<Facet>,
+    // The 80 header bytes of the binary STL the mesh was read from, carried
+    // through operations so write_binary can write them back out when asked
+    // to preserve them.
+    header: Option<Vec<u8>>,
+    // Byte order of that STL, which is written back the same way.
+    byte_order: ByteOrder,
+    // A label for the object, e.g. the name of an ASCII STL solid.
+    name: Option<String>,
+    // Per-vertex colors, one for each of vertices when present. Operations
+    // that keep the vertex list carry them along; those that merge, split
+    // or add vertices drop them.
+    colors: Option<Vec<Color>>,
+}
+
+impl Clone for Mesh {
+    fn clone(&self) -> Mesh {
+        self.with_parts(self.vertices.clone(), self.facets.clone(), self.colors.clone())
+    }
+}
+
+// Number of vertices and facets shown by the Debug summary of a Mesh.
+const DEBUG_PREVIEW: usize = 5;
+
+// A summary rather than a dump, since meshes easily run to millions of
+// facets: the counts and the first few vertices and facets. Use dump for
+// everything.
+impl fmt::Debug for Mesh {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "Mesh {{ {} vertices, {} facets", self.vertices.len(), self.facets.len()));
+        for (i, v) in self.vertices.iter().take(DEBUG_PREVIEW).enumerate() {
+            try!(write!(f, "\n  v[{}] {:?}", i, v));
+        }
+        if self.vertices.len() > DEBUG_PREVIEW {
+            try!(write!(f, "\n  ..."));
+        }
+        for (i, fc) in self.facets.iter().take(DEBUG_PREVIEW).enumerate() {
+            try!(write!(f, "\n  f[{}] {}-{}-{}", i, fc.v1, fc.v2, fc.v3));
+        }
+        if self.facets.len() > DEBUG_PREVIEW {
+            try!(write!(f, "\n  ..."));
+        }
+        write!(f, "\n}}")
+    }
+}
+
+// Default threshold on the cross product magnitude (twice the area) below
+// which a facet counts as degenerate.
+pub const DEGENERATE_EPSILON: f32 = 1e-6;
+
+// Default aspect ratio (longest edge squared over area) above which a facet
+// counts as a sliver. An equilateral triangle has about 2.3 and a right
+// isosceles one 4.
+pub const SLIVER_ASPECT: f32 = 20.0;
+
+// Reference points on a mesh's axis-aligned bounding box.
+#[derive(PartialEq, Debug, Copy)]
+pub enum BoundsPoint {
+    Min,
+    Max,
+    Center,
+}
+
+// Coordinate axes, e.g. to pick the one negated by a mirror.
+#[derive(PartialEq, Debug, Copy)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+// Byte order of the numbers in a binary STL. The format calls for little
+// endian, but some legacy exporters wrote their native big endian.
+#[derive(PartialEq, Debug, Copy)]
+pub enum ByteOrder {
+    Little,
+    Big,
 }
 
 impl Mesh {
@@ -150,6 +707,10 @@ impl Mesh {
         Mesh {
             vertices: Vec::new(),
             facets: Vec::new(),
+            header: None,
+            byte_order: ByteOrder::Little,
+            name: None,
+            colors: None,
         }
     }
 
@@ -163,81 +724,2439 @@ impl Mesh {
                 v1: v1,
                 v2: v2,
                 v3: v3,
+                abc: f.abc,
+                material: 0,
+                normal: Some(f.n),
             })
         }
         v
     }
 
+    // Builds a mesh from its parts as returned by vertices and facets. Every
+    // facet index must be within vertices.
+    pub fn new_from_parts(vertices: Vec<Vector3D>, facets: Vec<Facet>) -> Mesh {
+        assert!(facets.iter().all(|f| {
+            f.v1 < vertices.len() && f.v2 < vertices.len() && f.v3 < vertices.len()
+        }), "facet index out of range");
+        Mesh {
+            vertices: vertices,
+            facets: facets,
+            header: None,
+            byte_order: ByteOrder::Little,
+            name: None,
+            colors: None,
+        }
+    }
+
     pub fn new_from_stl(fv: &Vec<StlFacet>, vm: &VertexMap) -> Mesh {
         let vs = vm.vector();
         let fs = Mesh::indexed_vertices(fv, vm);
         Mesh {
             vertices: vs,
             facets: fs,
+            header: None,
+            byte_order: ByteOrder::Little,
+            name: None,
+            colors: None,
         }
     }
 
-    pub fn read(r: &mut BufferedReader<Reader>) -> Mesh {
+    // A cube with sides of length size, centered on the origin.
+    pub fn cube(size: f32) -> Mesh {
+        let h = size / 2.0;
+        // Vertex i has x, y and z from bits 0, 1 and 2 of i.
+        let vs = (0..8).map(|i| Vector3D::new(
+            if i & 1 == 0 { -h } else { h },
+            if i & 2 == 0 { -h } else { h },
+            if i & 4 == 0 { -h } else { h })).collect();
+        let corners = [
+            (0, 2, 3), (0, 3, 1), (4, 5, 7), (4, 7, 6),     // -Z, +Z
+            (0, 1, 5), (0, 5, 4), (2, 7, 3), (2, 6, 7),     // -Y, +Y
+            (0, 4, 6), (0, 6, 2), (1, 3, 7), (1, 7, 5),     // -X, +X
+        ];
+        let fs = corners.iter().map(|&(a, b, c)| Facet::new(a, b, c)).collect();
+        Mesh::new_from_parts(vs, fs)
+    }
 
-        let buf = [0; 80];
-        let header = match r.read(&buf) {
-            Ok(nread) => {
-                let hdr = String::from_utf8(&buf).unwrap();
-                println!("Header: \"{}\"", hdr);
-                hdr
+    // A UV sphere centered on the origin, with segments vertices around
+    // each ring of latitude and half as many bands from pole to pole.
+    // segments must be at least 3.
+    pub fn sphere(radius: f32, segments: usize) -> Mesh {
+        assert!(segments >= 3, "a sphere needs at least 3 segments");
+        let rings = max(segments / 2, 2);
+        let pi: f32 = Float::pi();
+        let mut vs = vec![Vector3D::new(0.0, 0.0, radius)];
+        for i in 1..rings {
+            let (sin_t, cos_t) = (pi * i as f32 / rings as f32).sin_cos();
+            for j in 0..segments {
+                let (sin_p, cos_p) = (2.0 * pi * j as f32 / segments as f32).sin_cos();
+                vs.push(Vector3D::new(radius * sin_t * cos_p, radius * sin_t * sin_p,
+                    radius * cos_t));
             }
-            Err(e) => {
-                println!("Truncated file: {}", e);
-                return Mesh{vertices: Vec::new(), facets: Vec::new()};
+        }
+        vs.push(Vector3D::new(0.0, 0.0, -radius));
+        let bottom = vs.len() - 1;
+        // Vertex j of ring i, counting rings from 1 below the top pole.
+        let at = |i: usize, j: usize| 1 + (i - 1) * segments + j % segments;
+        let mut fs: Vec<Facet> = Vec::new();
+        for j in 0..segments {
+            fs.push(Facet::new(0, at(1, j), at(1, j + 1)));
+            for i in 1..rings - 1 {
+                fs.push(Facet::new(at(i, j), at(i + 1, j), at(i + 1, j + 1)));
+                fs.push(Facet::new(at(i, j), at(i + 1, j + 1), at(i, j + 1)));
             }
-        };
+            fs.push(Facet::new(at(rings - 1, j), bottom, at(rings - 1, j + 1)));
+        }
+        Mesh::new_from_parts(vs, fs)
+    }
 
-        return if header.starts_with("solid ") {
-            println!("Is ASCII STL");
-            Mesh::read_ascii(r)
+    // A cylinder along Z centered on the origin, its round sides made of
+    // segments flat faces. segments must be at least 3.
+    pub fn cylinder(radius: f32, height: f32, segments: usize) -> Mesh {
+        assert!(segments >= 3, "a cylinder needs at least 3 segments");
+        let h = height / 2.0;
+        let pi: f32 = Float::pi();
+        let mut vs = vec![Vector3D::new(0.0, 0.0, -h), Vector3D::new(0.0, 0.0, h)];
+        for &z in [-h, h].iter() {
+            for j in 0..segments {
+                let (sin_p, cos_p) = (2.0 * pi * j as f32 / segments as f32).sin_cos();
+                vs.push(Vector3D::new(radius * cos_p, radius * sin_p, z));
+            }
         }
-        else {
-            println!("Is binary STL");
-            Mesh::read_binary(r)
+        let low = |j: usize| 2 + j % segments;
+        let high = |j: usize| 2 + segments + j % segments;
+        let mut fs: Vec<Facet> = Vec::new();
+        for j in 0..segments {
+            fs.push(Facet::new(0, low(j + 1), low(j)));
+            fs.push(Facet::new(1, high(j), high(j + 1)));
+            fs.push(Facet::new(high(j), low(j), low(j + 1)));
+            fs.push(Facet::new(high(j), low(j + 1), high(j + 1)));
         }
+        Mesh::new_from_parts(vs, fs)
     }
 
-    fn read_ascii(r: &mut BufferedReader<Reader>) -> Mesh {
-        //solid vcg
-        //  facet normal 7.733874e-001 -3.151335e-002 6.331499e-001
-        //    outer loop
-        //      vertex  2.137833e+001 5.385936e+001 1.350253e+001
-        //      vertex  2.341920e+001 4.700068e+001 1.066826e+001
-        //      vertex  2.859041e+001 5.985682e+001 4.991545e+000
-        //    endloop
-        //  endfacet
-        //endsolid vcg
-        for line in r.lines() {
-            print!("{}", line.unwrap());
+    // Triangle soup: each facet gets its own three vertices, in file order.
+    fn new_from_stl_unshared(fv: &Vec<StlFacet>) -> Mesh {
+        let mut vs: Vec<Vector3D> = Vec::with_capacity(fv.len() * 3);
+        let mut fs: Vec<Facet> = Vec::with_capacity(fv.len());
+        for f in fv.iter() {
+            let i = vs.len();
+            vs.push(f.v1);
+            vs.push(f.v2);
+            vs.push(f.v3);
+            fs.push(Facet { v1: i, v2: i + 1, v3: i + 2, abc: f.abc, material: 0,
+                            normal: Some(f.n) });
+        }
+        Mesh {
+            vertices: vs,
+            facets: fs,
+            header: None,
+            byte_order: ByteOrder::Little,
+            name: None,
+            colors: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.facets.is_empty()
+    }
+
+    // Writes every vertex and facet, one per line, in the same notation as
+    // the Debug summary.
+    pub fn dump<W: Writer>(&self, w: &mut W) -> IoResult<()> {
+        try!(write!(w, "{} vertices, {} facets\n", self.vertices.len(), self.facets.len()));
+        for (i, v) in self.vertices.iter().enumerate() {
+            try!(write!(w, "v[{}] {:?}\n", i, v));
         }
-        Mesh::new() // TODO
+        for (i, f) in self.facets.iter().enumerate() {
+            try!(write!(w, "f[{}] {}-{}-{} [{:X}]\n", i, f.v1, f.v2, f.v3, f.abc));
+        }
+        Ok(())
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_ref().map(|n| n.as_slice())
+    }
+
+    pub fn with_name(self, name: &str) -> Mesh {
+        Mesh { name: Some(name.to_string()), ..self }
+    }
+
+    // A mesh of the given parts that keeps this one's header, byte order and
+    // name, for operations that build a new vertex or facet list.
+    fn with_parts(&self, vertices: Vec<Vector3D>, facets: Vec<Facet>,
+                  colors: Option<Vec<Color>>) -> Mesh {
+        Mesh {
+            vertices: vertices,
+            facets: facets,
+            header: self.header.clone(),
+            byte_order: self.byte_order,
+            name: self.name.clone(),
+            colors: colors,
+        }
+    }
+
+    pub fn colors(&self) -> Option<&[Color]> {
+        self.colors.as_ref().map(|c| c.as_slice())
+    }
+
+    // Attaches a color to every vertex, in the same order as vertices.
+    pub fn with_colors(self, colors: Vec<Color>) -> Mesh {
+        assert!(colors.len() == self.vertices.len(), "one color per vertex needed");
+        Mesh { colors: Some(colors), ..self }
+    }
+
+    // What writers call the mesh when it has no name of its own.
+    fn label(&self) -> &str {
+        self.name().unwrap_or("mesh")
+    }
+
+    pub fn header(&self) -> Option<&[u8]> {
+        self.header.as_ref().map(|h| h.as_slice())
+    }
+
+    // Replaces the STL header kept for the mesh, which write_binary writes
+    // when asked to preserve it; None means meshman's own.
+    pub fn set_header(&mut self, header: Option<Vec<u8>>) {
+        self.header = header;
+    }
+
+    pub fn vertices(&self) -> &[Vector3D] {
+        self.vertices.as_slice()
+    }
+
+    pub fn facets(&self) -> &[Facet] {
+        self.facets.as_slice()
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.vertices.len()
+    }
+
+    pub fn facet_count(&self) -> usize {
+        self.facets.len()
+    }
+
+    // A k-d tree over the vertices for nearest-vertex queries. It borrows
+    // the mesh and is built each time this is called.
+    pub fn build_kdtree(&self) -> KdTree {
+        KdTree::new(self.vertices.as_slice())
+    }
+
+    // Returns the (min, max) corners of the axis-aligned bounding box, or
+    // None for a mesh with no vertices.
+    pub fn bounding_box(&self) -> Option<(Vector3D, Vector3D)> {
+        if self.vertices.is_empty() {
+            return None;
+        }
+        let mut min = self.vertices[0];
+        let mut max = self.vertices[0];
+        for v in self.vertices.iter() {
+            min.x = min.x.min(v.x);
+            min.y = min.y.min(v.y);
+            min.z = min.z.min(v.z);
+            max.x = max.x.max(v.x);
+            max.y = max.y.max(v.y);
+            max.z = max.z.max(v.z);
+        }
+        Some((min, max))
+    }
+
+    // Extent of the bounding box along each axis (max - min).
+    pub fn dimensions(&self) -> Option<Vector3D> {
+        self.bounding_box().map(|(min, max)| Vector3D {
+            x: max.x - min.x,
+            y: max.y - min.y,
+            z: max.z - min.z,
+        })
+    }
+
+    // Center and radius of a sphere enclosing every vertex, by Ritter's
+    // method: start from the span between a vertex far from the first one
+    // and the vertex farthest from that, then grow the sphere just enough to
+    // take in each vertex still outside it. Within a few percent of the
+    // smallest sphere. An empty mesh gets a zero sphere at the origin.
+    pub fn bounding_sphere(&self) -> (Vector3D, f32) {
+        if self.vertices.is_empty() {
+            return (Vector3D::new(0.0, 0.0, 0.0), 0.0);
+        }
+        let farthest = |from: Vector3D| -> Vector3D {
+            *self.vertices.iter().fold(&self.vertices[0], |best, v| {
+                if (*v - from).length() > (*best - from).length() { v } else { best }
+            })
+        };
+        let a = farthest(self.vertices[0]);
+        let b = farthest(a);
+        let mut center = (a + b) * 0.5;
+        let mut radius = (b - a).length() * 0.5;
+        for &v in self.vertices.iter() {
+            let d = (v - center).length();
+            if d > radius {
+                let grown = (radius + d) * 0.5;
+                center = center + (v - center) * ((grown - radius) / d);
+                radius = grown;
+            }
+        }
+        (center, radius)
+    }
+
+    // How many layers of the given thickness it takes to build the mesh up
+    // to its full height, or None for a mesh with no vertices.
+    pub fn layer_count(&self, thickness: f32) -> Option<u32> {
+        self.dimensions().map(|size| (size.z / thickness).ceil() as u32)
     }
 
-    fn read_binary(r: &mut Reader) -> Mesh {
-        let facet_count = match r.read_le_u32() {
-            Ok(c) => { println!("Facets: {}", c); c},
-            Err(e) => { println!("Truncated file: {}", e); 0},
+    pub fn bounds_point(&self, point: BoundsPoint) -> Option<Vector3D> {
+        let (min, max) = match self.bounding_box() {
+            Some(bb) => bb,
+            None => return None,
         };
+        Some(match point {
+            BoundsPoint::Min => min,
+            BoundsPoint::Max => max,
+            BoundsPoint::Center => Vector3D {
+                x: (min.x + max.x) / 2.0,
+                y: (min.y + max.y) / 2.0,
+                z: (min.z + max.z) / 2.0,
+            },
+        })
+    }
 
-        let mut facets: Vec<StlFacet> = Vec::with_capacity(facet_count as usize);
-        let mut vertices = VertexMap::new();
-        println!("Collections ready");
-
-        for fi in range(0, facet_count) {
-            let f = StlFacet::read(r);
-            let v1i = vertices.add(f.v1);
-            let v2i = vertices.add(f.v2);
-            let v3i = vertices.add(f.v3);
-            println!("  Facet[{}]: {:?} => {}-{}-{}", fi, f, v1i, v2i, v3i);
-            facets.push(f);
+    // Enclosed volume as the sum of the signed volumes of the tetrahedra
+    // formed by each facet and the origin. Only meaningful for a closed mesh
+    // with consistent winding; outward-facing facets give a positive result.
+    pub fn volume(&self) -> f32 {
+        let mut total = 0.0f64;
+        for f in self.facets.iter() {
+            let a = &self.vertices[f.v1];
+            let b = &self.vertices[f.v2];
+            let c = &self.vertices[f.v3];
+            total += a.dot(&b.cross(c)) as f64;
         }
-        println!("Vertices: {}", vertices.len());
+        (total / 6.0) as f32
+    }
+
+    // Center of mass of a solid of uniform density: the centroids of the
+    // tetrahedra used by volume, weighted by their signed volumes. Like
+    // volume it is only physically meaningful for a watertight mesh with
+    // consistent winding. Open meshes, and closed ones enclosing no volume,
+    // get the average of their vertices instead. None if the mesh is empty.
+    pub fn centroid(&self) -> Option<Vector3D> {
+        if self.vertices.is_empty() {
+            return None;
+        }
+        let mut weighted = [0.0f64; 3];
+        let mut total = 0.0f64;
+        if self.is_watertight() {
+            for f in self.facets.iter() {
+                let a = &self.vertices[f.v1];
+                let b = &self.vertices[f.v2];
+                let c = &self.vertices[f.v3];
+                let v = a.dot(&b.cross(c)) as f64;
+                weighted[0] += v * (a.x + b.x + c.x) as f64;
+                weighted[1] += v * (a.y + b.y + c.y) as f64;
+                weighted[2] += v * (a.z + b.z + c.z) as f64;
+                total += v;
+            }
+        }
+        if total != 0.0 {
+            // Each tetrahedron's centroid is a quarter of the way from the
+            // origin to the sum of its facet's corners.
+            let d = total * 4.0;
+            return Some(Vector3D::new((weighted[0] / d) as f32, (weighted[1] / d) as f32,
+                (weighted[2] / d) as f32));
+        }
+        let n = self.vertices.len() as f64;
+        let mut sum = [0.0f64; 3];
+        for v in self.vertices.iter() {
+            sum[0] += v.x as f64;
+            sum[1] += v.y as f64;
+            sum[2] += v.z as f64;
+        }
+        Some(Vector3D::new((sum[0] / n) as f32, (sum[1] / n) as f32, (sum[2] / n) as f32))
+    }
+
+    // The corners of each facet, in facet order, looked up in the vertex
+    // list as the iterator goes.
+    pub fn triangles(&self) -> Triangles {
+        Triangles { vertices: self.vertices.as_slice(), facets: self.facets.iter() }
+    }
+
+    // Total area of all facets, each being half the magnitude of the cross
+    // product of two of its edges.
+    pub fn surface_area(&self) -> f32 {
+        let mut total = 0.0f64;
+        for t in self.triangles() {
+            total += triangle_area(&t) as f64;
+        }
+        total as f32
+    }
+
+    // Area of each facet, in facet order.
+    pub fn facet_areas(&self) -> Vec<f32> {
+        self.triangles().map(|t| triangle_area(&t)).collect()
+    }
+
+    fn facet_area(&self, f: &Facet) -> f32 {
+        triangle_area(&[self.vertices[f.v1], self.vertices[f.v2], self.vertices[f.v3]])
+    }
+
+    // Normal of the largest flat region of the surface, for picking a side
+    // to print on. Facets are grouped by their unit normal rounded to two
+    // decimal places, the group with the most area wins and its normal is
+    // the area-weighted average of its facets'. Parallel facets group
+    // together even when they are not coplanar, so this is a heuristic, not
+    // the orientation needing the least support. None if no facet has area.
+    pub fn largest_flat_face_normal(&self) -> Option<Vector3D> {
+        let mut groups: HashMap<(i32, i32, i32), (f64, Vector3D)> = HashMap::new();
+        for t in self.triangles() {
+            let cross = (t[1] - t[0]).cross(&(t[2] - t[0]));
+            let area = cross.length() / 2.0;
+            if area == 0.0 {
+                continue;
+            }
+            let n = cross.normalize();
+            let key = ((n.x * 100.0).round() as i32, (n.y * 100.0).round() as i32,
+                       (n.z * 100.0).round() as i32);
+            let zero = Vector3D::new(0.0, 0.0, 0.0);
+            let group = groups.entry(key).get().unwrap_or_else(|v| v.insert((0.0, zero)));
+            group.0 += area as f64;
+            group.1 = group.1 + cross;
+        }
+        let mut best: Option<(f64, Vector3D)> = None;
+        for &(area, sum) in groups.values() {
+            if best.map_or(true, |(a, _)| area > a) {
+                best = Some((area, sum));
+            }
+        }
+        best.map(|(_, sum)| sum.normalize())
+    }
+
+    // Unit normal of each facet from its winding by the right-hand rule. A
+    // degenerate, zero-area facet gets a zero normal.
+    pub fn recomputed_normals(&self) -> Vec<Vector3D> {
+        self.facets.iter().map(|f| self.facet_normal(f)).collect()
+    }
+
+    fn facet_normal(&self, f: &Facet) -> Vector3D {
+        let a = &self.vertices[f.v1];
+        let b = &self.vertices[f.v2];
+        let c = &self.vertices[f.v3];
+        let u = *b - *a;
+        let v = *c - *a;
+        u.cross(&v).normalize()
+    }
+
+    // Unit normal at each vertex, the area-weighted average of the normals
+    // of the facets around it. A vertex no facet uses gets a zero normal.
+    pub fn vertex_normals(&self) -> Vec<Vector3D> {
+        let zero = Vector3D::new(0.0, 0.0, 0.0);
+        let mut normals: Vec<Vector3D> = (0..self.vertices.len()).map(|_| zero).collect();
+        for f in self.facets.iter() {
+            // The cross product's length is twice the area, so summing them
+            // weights each facet by its area.
+            let (a, b, c) = (self.vertices[f.v1], self.vertices[f.v2], self.vertices[f.v3]);
+            let cross = (b - a).cross(&(c - a));
+            for &i in [f.v1, f.v2, f.v3].iter() {
+                normals[i] = normals[i] + cross;
+            }
+        }
+        normals.iter().map(|n| n.normalize()).collect()
+    }
+
+    // Removes the facets degenerate_facets would list, then the vertices
+    // left unused.
+    pub fn remove_degenerate(&self, eps: f32) -> Mesh {
+        let facets = self.facets.iter().filter(|f| self.facet_area(f) * 2.0 >= eps)
+            .map(|f| *f)
+            .collect();
+        self.with_parts(self.vertices.clone(), facets, self.colors.clone()).compact()
+    }
+
+    // Facets whose normal as read from STL is more than max_angle radians
+    // from the normal of their winding, with that angle, worst first.
+    // Facets with no stored normal, a zero one or no area are left out.
+    pub fn normal_mismatches(&self, max_angle: f32) -> Vec<(usize, f32)> {
+        let mut v: Vec<(usize, f32)> = Vec::new();
+        for (i, f) in self.facets.iter().enumerate() {
+            let stored = match f.normal {
+                Some(n) if n.length() > 0.0 => n.normalize(),
+                _ => continue,
+            };
+            let computed = self.facet_normal(f);
+            if computed.length() == 0.0 {
+                continue;
+            }
+            let angle = stored.dot(&computed).max(-1.0).min(1.0).acos();
+            if angle > max_angle {
+                v.push((i, angle));
+            }
+        }
+        v.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(::std::cmp::Ordering::Equal));
+        v
+    }
+
+    // Indices of facets whose cross product magnitude is below eps, i.e.
+    // whose vertices are coincident or collinear.
+    pub fn degenerate_facets(&self, eps: f32) -> Vec<usize> {
+        let mut v: Vec<usize> = Vec::new();
+        for (i, f) in self.facets.iter().enumerate() {
+            if self.facet_area(f) * 2.0 < eps {
+                v.push(i);
+            }
+        }
+        v
+    }
+
+    // Gives the facets whose centroid lies between heights z0 and z1,
+    // inclusive, material id. Other facets keep the material they had.
+    pub fn assign_material(&self, z0: f32, z1: f32, id: u32) -> Mesh {
+        let vs = &self.vertices;
+        let fs = self.facets.iter().map(|f| {
+            let z = (vs[f.v1].z + vs[f.v2].z + vs[f.v3].z) / 3.0;
+            if z >= z0 && z <= z1 { Facet { material: id, ..*f } } else { *f }
+        }).collect();
+        self.with_parts(self.vertices.clone(), fs, self.colors.clone())
+    }
+
+    // Whether any vertex has a NaN or infinite coordinate, as a corrupt
+    // binary STL can give.
+    pub fn has_nan(&self) -> bool {
+        self.vertices.iter().any(|v| !v.is_finite())
+    }
+
+    // Indices of vertices with a NaN or infinite coordinate.
+    pub fn invalid_vertices(&self) -> Vec<usize> {
+        (0..self.vertices.len()).filter(|&i| !self.vertices[i].is_finite()).collect()
+    }
+
+    // Removes the facets using a vertex with a NaN or infinite coordinate,
+    // then the vertices left unused.
+    pub fn drop_invalid(&self) -> Mesh {
+        let vs = &self.vertices;
+        let facets = self.facets.iter()
+            .filter(|f| vs[f.v1].is_finite() && vs[f.v2].is_finite() && vs[f.v3].is_finite())
+            .map(|f| *f)
+            .collect();
+        self.with_parts(self.vertices.clone(), facets, self.colors.clone()).compact()
+    }
+
+    // Aspect ratio of each facet as its longest edge squared over its area,
+    // which grows without bound as a facet thins out. Zero-area facets get
+    // infinity.
+    pub fn aspect_ratios(&self) -> Vec<f32> {
+        self.triangles().map(|t| {
+            let sq = |p: Vector3D, q: Vector3D| {
+                let d = q - p;
+                d.dot(&d)
+            };
+            let longest = sq(t[0], t[1]).max(sq(t[1], t[2])).max(sq(t[2], t[0]));
+            let area = triangle_area(&t);
+            if area > 0.0 { longest / area } else { Float::infinity() }
+        }).collect()
+    }
+
+    // Indices of facets whose aspect ratio is above max_aspect.
+    pub fn sliver_facets(&self, max_aspect: f32) -> Vec<usize> {
+        self.aspect_ratios().iter().enumerate()
+            .filter(|&(_, &r)| r > max_aspect)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    // The segments where facets cross the plane Z = z, one per facet, each
+    // running between the two facet edges that cross it. Vertices exactly
+    // on the plane count as above it, so a facet touching the plane along
+    // an edge gives that edge once (from the facet below) rather than
+    // twice, and a facet touching it at a single vertex gives nothing.
+    // Facets lying in the plane have no single crossing segment and are
+    // left out.
+    pub fn slice_z(&self, z: f32) -> Vec<(Vector3D, Vector3D)> {
+        let mut segments: Vec<(Vector3D, Vector3D)> = Vec::new();
+        for f in self.facets.iter() {
+            let corners = [self.vertices[f.v1], self.vertices[f.v2], self.vertices[f.v3]];
+            let mut points: Vec<Vector3D> = Vec::with_capacity(2);
+            for i in 0..3 {
+                let (a, b) = (corners[i], corners[(i + 1) % 3]);
+                let (da, db) = (a.z - z, b.z - z);
+                if (da >= 0.0) != (db >= 0.0) {
+                    let t = da / (da - db);
+                    points.push(Vector3D {
+                        x: a.x + (b.x - a.x) * t,
+                        y: a.y + (b.y - a.y) * t,
+                        z: z,
+                    });
+                }
+            }
+            if points.len() == 2 && points[0] != points[1] {
+                segments.push((points[0], points[1]));
+            }
+        }
+        segments
+    }
+
+    // Indices of vertices more than eps away from the vertex at the same
+    // index in other, in any coordinate, and of facets whose corners are
+    // numbered differently. Only indices both meshes have are compared.
+    pub fn differing_vertices(&self, other: &Mesh, eps: f32) -> (Vec<usize>, Vec<usize>) {
+        let vs = self.vertices.iter().zip(other.vertices.iter()).enumerate()
+            .filter(|&(_, (a, b))| !a.near(b, eps))
+            .map(|(i, _)| i)
+            .collect();
+        let fs = self.facets.iter().zip(other.facets.iter()).enumerate()
+            .filter(|&(_, (a, b))| (a.v1, a.v2, a.v3) != (b.v1, b.v2, b.v3))
+            .map(|(i, _)| i)
+            .collect();
+        (vs, fs)
+    }
+
+    // Indices of facets with no counterpart in other, comparing positions
+    // rather than indices so that meshes numbered differently can match.
+    // Each facet is keyed by its corners, rotated to start at the lowest one
+    // so the winding is kept; both lists are sorted by key and compared
+    // pairwise within eps. Differences big enough to change the sort order
+    // show up as a run of mismatches rather than one.
+    pub fn differing_facets(&self, other: &Mesh, eps: f32) -> Vec<usize> {
+        let keyed = |m: &Mesh| -> Vec<(usize, (Vector3D, Vector3D, Vector3D))> {
+            let mut keys: Vec<_> = m.facets.iter().enumerate().map(|(i, f)| {
+                let (a, b, c) = (m.vertices[f.v1], m.vertices[f.v2], m.vertices[f.v3]);
+                let key = if a <= b && a <= c { (a, b, c) } else if b <= c { (b, c, a) } else { (c, a, b) };
+                (i, key)
+            }).collect();
+            keys.sort_by(|x, y| x.1.partial_cmp(&y.1).unwrap_or(::std::cmp::Ordering::Equal));
+            keys
+        };
+        let (ours, theirs) = (keyed(self), keyed(other));
+        let mut v: Vec<usize> = Vec::new();
+        for (i, &(fi, (a, b, c))) in ours.iter().enumerate() {
+            match theirs.get(i) {
+                Some(&(_, (x, y, z))) if a.near(&x, eps) && b.near(&y, eps) && c.near(&z, eps) => {}
+                _ => v.push(fi),
+            }
+        }
+        v.sort();
+        v
+    }
+
+    // Number of facets using each undirected edge, keyed (low, high).
+    fn edge_counts(&self) -> HashMap<(usize, usize), usize> {
+        let mut edges: HashMap<(usize, usize), usize> = HashMap::new();
+        for f in self.facets.iter() {
+            for &(a, b) in [(f.v1, f.v2), (f.v2, f.v3), (f.v3, f.v1)].iter() {
+                let key = if a < b { (a, b) } else { (b, a) };
+                *edges.entry(key).get().unwrap_or_else(|v| v.insert(0)) += 1;
+            }
+        }
+        edges
+    }
+
+    // Length of each edge, counting an edge shared by several facets once,
+    // in order of the edges' vertex indices.
+    pub fn edge_lengths(&self) -> Vec<f32> {
+        let mut edges: Vec<(usize, usize)> = self.edge_counts().keys().map(|&e| e).collect();
+        edges.sort();
+        edges.iter().map(|&(a, b)| (self.vertices[a] - self.vertices[b]).length()).collect()
+    }
+
+    // Edges shared by some number of facets other than two, sorted by
+    // vertex index.
+    pub fn non_manifold_edges(&self) -> Vec<(usize, usize)> {
+        let mut v: Vec<(usize, usize)> = self.edge_counts().iter()
+            .filter(|&(_, &n)| n != 2)
+            .map(|(&e, _)| e)
+            .collect();
+        v.sort();
+        v
+    }
+
+    // Edges shared by more than two facets, sorted by vertex index. Unlike
+    // open edges these can't be fixed by filling a hole.
+    pub fn overshared_edges(&self) -> Vec<(usize, usize)> {
+        let mut v: Vec<(usize, usize)> = self.edge_counts().iter()
+            .filter(|&(_, &n)| n > 2)
+            .map(|(&e, _)| e)
+            .collect();
+        v.sort();
+        v
+    }
+
+    // Chains the edges used by only one facet into loops of vertex indices,
+    // one per hole. Each loop follows the direction its edges have in their
+    // facets. Where the open edges don't close up, the chain that was
+    // followed is returned as it is.
+    pub fn boundary_loops(&self) -> Vec<Vec<usize>> {
+        let counts = self.edge_counts();
+        let mut open: Vec<(usize, usize)> = Vec::new();
+        for f in self.facets.iter() {
+            for &(a, b) in [(f.v1, f.v2), (f.v2, f.v3), (f.v3, f.v1)].iter() {
+                let key = if a < b { (a, b) } else { (b, a) };
+                if counts.get(&key) == Some(&1) {
+                    open.push((a, b));
+                }
+            }
+        }
+        open.sort();
+        let mut next: HashMap<usize, Vec<usize>> = HashMap::new();
+        for &(a, b) in open.iter().rev() {
+            next.entry(a).get().unwrap_or_else(|v| v.insert(Vec::new())).push(b);
+        }
+        let mut loops: Vec<Vec<usize>> = Vec::new();
+        for &(start, _) in open.iter() {
+            let mut lp = vec![start];
+            let mut at = start;
+            loop {
+                let to = match next.get_mut(&at).and_then(|v| v.pop()) {
+                    Some(to) => to,
+                    None => break,
+                };
+                if to == start {
+                    break;
+                }
+                lp.push(to);
+                at = to;
+            }
+            if lp.len() > 1 {
+                loops.push(lp);
+            }
+        }
+        loops
+    }
+
+    // Closes each hole found by boundary_loops with a fan of facets from the
+    // loop's first vertex, which is fine for small, roughly planar holes.
+    // The loops run the same way as their edges in the neighbouring facets,
+    // so the fan goes the other way round to match their winding. Loops of
+    // fewer than three vertices and chains that don't close are left alone.
+    pub fn fill_holes(&self) -> Mesh {
+        let counts = self.edge_counts();
+        let mut fs = self.facets.clone();
+        for lp in self.boundary_loops().iter() {
+            if lp.len() < 3 {
+                continue;
+            }
+            let (first, last) = (lp[0], lp[lp.len() - 1]);
+            let closing = if first < last { (first, last) } else { (last, first) };
+            if counts.get(&closing) != Some(&1) {
+                continue;
+            }
+            for i in 1..lp.len() - 1 {
+                fs.push(Facet::new(first, lp[i + 1], lp[i]));
+            }
+        }
+        self.with_parts(self.vertices.clone(), fs, self.colors.clone())
+    }
+
+    // A mesh is watertight when every edge is shared by exactly two facets.
+    pub fn is_watertight(&self) -> bool {
+        !self.facets.is_empty() && self.non_manifold_edges().is_empty()
+    }
+
+    // Splits the mesh into pieces whose facets are connected through shared
+    // vertices, using union-find over the facet corners. Each piece gets its
+    // own renumbered vertex list and this mesh's header, byte order and
+    // name; pieces come out in the order of their first facet, and a lone
+    // facet is a piece of its own.
+    pub fn connected_components(&self) -> Vec<Mesh> {
+        fn root(parent: &mut Vec<usize>, i: usize) -> usize {
+            let mut r = i;
+            while parent[r] != r {
+                r = parent[r];
+            }
+            let mut j = i;
+            while parent[j] != r {
+                let next = parent[j];
+                parent[j] = r;
+                j = next;
+            }
+            r
+        }
+        let mut parent: Vec<usize> = (0..self.vertices.len()).collect();
+        for f in self.facets.iter() {
+            let a = root(&mut parent, f.v1);
+            let b = root(&mut parent, f.v2);
+            parent[b] = a;
+            let c = root(&mut parent, f.v3);
+            parent[c] = a;
+        }
+
+        let mut component_of: HashMap<usize, usize> = HashMap::new();
+        let mut parts: Vec<Mesh> = Vec::new();
+        let mut remap: Vec<Option<usize>> = (0..self.vertices.len()).map(|_| None).collect();
+        for f in self.facets.iter() {
+            let r = root(&mut parent, f.v1);
+            let ci = match component_of.get(&r) {
+                Some(&ci) => ci,
+                None => {
+                    component_of.insert(r, parts.len());
+                    parts.push(self.with_parts(Vec::new(), Vec::new(), None));
+                    parts.len() - 1
+                }
+            };
+            let part = &mut parts[ci];
+            let (v1, v2, v3) = {
+                let mut corner = |vi: usize| -> usize {
+                    match remap[vi] {
+                        Some(ni) => ni,
+                        None => {
+                            let ni = part.vertices.len();
+                            part.vertices.push(self.vertices[vi]);
+                            remap[vi] = Some(ni);
+                            ni
+                        }
+                    }
+                };
+                (corner(f.v1), corner(f.v2), corner(f.v3))
+            };
+            part.facets.push(Facet { v1: v1, v2: v2, v3: v3, ..*f });
+        }
+        parts
+    }
+
+    // Applies f to every vertex, keeping the facets as they are apart from
+    // their stored normals, which can't be told where an arbitrary f takes
+    // them and are dropped.
+    pub fn map_vertices<F: Fn(Vector3D) -> Vector3D + Sync>(&self, f: F) -> Mesh {
+        self.with_parts(self.mapped_vertices(f), self.facets_without_normals(), self.colors.clone())
+    }
+
+    // The vertices put through f, which the per-vertex transforms below are
+    // all built on. With more than one thread configured, large meshes are
+    // cut into one chunk per thread and the results joined back in order.
+    fn mapped_vertices<F: Fn(Vector3D) -> Vector3D + Sync>(&self, f: F) -> Vec<Vector3D> {
+        let n = threads();
+        if n == 1 || self.vertices.len() < PARALLEL_MIN_VERTICES {
+            self.vertices.iter().map(|v| f(*v)).collect()
+        } else {
+            let f = &f;
+            let chunk = (self.vertices.len() + n - 1) / n;
+            let guards: Vec<thread::JoinGuard<Vec<Vector3D>>> = self.vertices.chunks(chunk)
+                .map(|c| thread::scoped(move || c.iter().map(|v| f(*v)).collect()))
+                .collect();
+            let mut vs: Vec<Vector3D> = Vec::with_capacity(self.vertices.len());
+            for g in guards.into_iter() {
+                vs.push_all(g.join().as_slice());
+            }
+            vs
+        }
+    }
+
+    fn facets_without_normals(&self) -> Vec<Facet> {
+        self.facets.iter().map(|f| Facet { normal: None, ..*f }).collect()
+    }
+
+    // The facets with their stored normals taken through m as its vertices
+    // are, for the transforms that have a matrix. See
+    // Matrix4::transform_normal.
+    fn facets_transformed(&self, m: &Matrix4) -> Vec<Facet> {
+        self.facets.iter().map(|f| Facet {
+            normal: f.normal.and_then(|n| m.transform_normal(&n)).map(|n| n.normalize()),
+            ..*f
+        }).collect()
+    }
+
+    pub fn translate(&self, offset: Vector3D) -> Mesh {
+        self.with_parts(self.mapped_vertices(|v| v + offset), self.facets.clone(),
+                        self.colors.clone())
+    }
+
+    // Scales each vertex component-wise, so non-uniform factors stretch the
+    // mesh along individual axes.
+    pub fn scale(&self, factors: Vector3D) -> Mesh {
+        let vs = self.mapped_vertices(|v| Vector3D {
+            x: v.x * factors.x,
+            y: v.y * factors.y,
+            z: v.z * factors.z,
+        });
+        self.with_parts(vs, self.facets_transformed(&Matrix4::scaling(factors)),
+                        self.colors.clone())
+    }
+
+    // Moves every vertex distance along its vertex normal, outward for a
+    // positive distance on a mesh with outward-facing facets. Nothing stops
+    // facets crossing each other, which large offsets do in concave regions
+    // (inward ones around convex features), so keep the distance small next
+    // to the features of the mesh.
+    pub fn offset(&self, distance: f32) -> Mesh {
+        let normals = self.vertex_normals();
+        let vs = self.vertices.iter().zip(normals.iter())
+            .map(|(&v, &n)| v + n * distance)
+            .collect();
+        self.with_parts(vs, self.facets_without_normals(), self.colors.clone())
+    }
+
+    // Rotates the mesh by angle radians about an axis through the origin,
+    // counter-clockwise when looking down the axis, using Rodrigues'
+    // formula. The axis need not be unit length; a zero axis leaves the
+    // mesh unchanged.
+    pub fn rotate(&self, axis: Vector3D, angle: f32) -> Mesh {
+        let k = axis.normalize();
+        let (sin, cos) = angle.sin_cos();
+        let vs = self.mapped_vertices(|v| {
+            let kxv = k.cross(&v);
+            let kdv = k.dot(&v) * (1.0 - cos);
+            Vector3D {
+                x: v.x * cos + kxv.x * sin + k.x * kdv,
+                y: v.y * cos + kxv.y * sin + k.y * kdv,
+                z: v.z * cos + kxv.z * sin + k.z * kdv,
+            }
+        });
+        self.with_parts(vs, self.facets_transformed(&Matrix4::rotation(axis, angle)),
+                        self.colors.clone())
+    }
+
+    // Rotates the mesh about an axis through the origin so that normal
+    // points straight down, -Z, as a face resting on the build plate would.
+    pub fn rest_on(&self, normal: Vector3D) -> Mesh {
+        let down = Vector3D::new(0.0, 0.0, -1.0);
+        let n = normal.normalize();
+        let axis = n.cross(&down);
+        if axis.length() < 1e-6 {
+            // Already straight down, or straight up and turned over about X.
+            let angle = if n.z > 0.0 { Float::pi() } else { 0.0 };
+            return self.rotate(Vector3D::new(1.0, 0.0, 0.0), angle);
+        }
+        self.rotate(axis, n.dot(&down).max(-1.0).min(1.0).acos())
+    }
+
+    // Applies an affine (or projective) transform to every vertex. A
+    // transform with a negative linear determinant reflects the mesh, so the
+    // winding is reversed as in mirror to keep the facets facing outward.
+    // Stored normals follow, except under a projective transform.
+    pub fn transform(&self, m: &Matrix4) -> Mesh {
+        let vs = self.mapped_vertices(|v| m.transform_point(&v));
+        let fs = self.facets_transformed(m);
+        let fs = if m.linear_determinant() >= 0.0 {
+            fs
+        } else {
+            fs.iter().map(|f| f.reversed()).collect()
+        };
+        self.with_parts(vs, fs, self.colors.clone())
+    }
+
+    // Reverses the winding of every facet, turning an inside-out mesh the
+    // right way round. Normals are derived from the winding, so this flips
+    // them too, stored ones included.
+    pub fn flip_normals(&self) -> Mesh {
+        let fs = self.facets.iter().map(|f| f.reversed()).collect();
+        self.with_parts(self.vertices.clone(), fs, self.colors.clone())
+    }
+
+    // Makes neighbouring facets agree on their winding: facets sharing an
+    // edge must run along it in opposite directions. Starting from the first
+    // facet of each connected piece, which keeps its winding, a breadth
+    // first walk flips each neighbour that runs the same way as the facet
+    // it was reached from. Only edges with exactly two facets are crossed;
+    // across non-manifold edges there is no single right answer. Whether a
+    // piece ends up facing outward depends on its first facet; follow with
+    // flip-normals if it doesn't.
+    pub fn orient_consistently(&self) -> Mesh {
+        let mut by_edge: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        for (fi, f) in self.facets.iter().enumerate() {
+            for &(a, b) in [(f.v1, f.v2), (f.v2, f.v3), (f.v3, f.v1)].iter() {
+                let key = if a < b { (a, b) } else { (b, a) };
+                by_edge.entry(key).get().unwrap_or_else(|v| v.insert(Vec::new())).push(fi);
+            }
+        }
+        let runs = |f: &Facet, a: usize, b: usize| {
+            (f.v1 == a && f.v2 == b) || (f.v2 == a && f.v3 == b) || (f.v3 == a && f.v1 == b)
+        };
+        let mut flipped: Vec<Option<bool>> = (0..self.facets.len()).map(|_| None).collect();
+        let mut queue: VecDeque<usize> = VecDeque::new();
+        for seed in 0..self.facets.len() {
+            if flipped[seed].is_some() {
+                continue;
+            }
+            flipped[seed] = Some(false);
+            queue.push_back(seed);
+            while let Some(fi) = queue.pop_front() {
+                let f = &self.facets[fi];
+                let flip = flipped[fi].unwrap();
+                for &(a, b) in [(f.v1, f.v2), (f.v2, f.v3), (f.v3, f.v1)].iter() {
+                    // The edge as this facet runs along it once oriented.
+                    let (a, b) = if flip { (b, a) } else { (a, b) };
+                    let key = if a < b { (a, b) } else { (b, a) };
+                    let sharing = match by_edge.get(&key) {
+                        Some(s) if s.len() == 2 => s,
+                        _ => continue,
+                    };
+                    let gi = if sharing[0] == fi { sharing[1] } else { sharing[0] };
+                    if flipped[gi].is_none() {
+                        flipped[gi] = Some(runs(&self.facets[gi], a, b));
+                        queue.push_back(gi);
+                    }
+                }
+            }
+        }
+        let fs = self.facets.iter().zip(flipped.iter()).map(|(f, flip)| {
+            if flip.unwrap() { f.reversed() } else { *f }
+        }).collect();
+        self.with_parts(self.vertices.clone(), fs, self.colors.clone())
+    }
+
+    // Reflects the mesh through the plane perpendicular to axis at the
+    // origin, e.g. Axis::X mirrors across the YZ plane. Reflection reverses
+    // the winding, so v2 and v3 of every facet are swapped to keep the
+    // facets facing outward.
+    pub fn mirror(&self, axis: Axis) -> Mesh {
+        let vs = self.mapped_vertices(|v| match axis {
+            Axis::X => Vector3D { x: -v.x, y: v.y, z: v.z },
+            Axis::Y => Vector3D { x: v.x, y: -v.y, z: v.z },
+            Axis::Z => Vector3D { x: v.x, y: v.y, z: -v.z },
+        });
+        let fs = self.facets_transformed(&Matrix4::reflection(axis)).iter()
+            .map(|f| f.reversed()).collect();
+        self.with_parts(vs, fs, self.colors.clone())
+    }
+
+    // Translates the mesh so the given bounding box point lands on target.
+    // An empty mesh has no bounding box and is returned unchanged.
+    pub fn move_to(&self, point: BoundsPoint, target: Vector3D) -> Mesh {
+        let from = match self.bounds_point(point) {
+            Some(p) => p,
+            None => return self.clone(),
+        };
+        self.translate(Vector3D {
+            x: target.x - from.x,
+            y: target.y - from.y,
+            z: target.z - from.z,
+        })
+    }
+
+    // Combines two meshes into one, appending other's vertices and rebasing
+    // its facet indices past ours. Coincident vertices are not merged.
+    pub fn merge(&self, other: &Mesh) -> Mesh {
+        let base = self.vertices.len();
+        let mut vs = self.vertices.clone();
+        vs.push_all(other.vertices.as_slice());
+        let mut fs = self.facets.clone();
+        for f in other.facets.iter() {
+            fs.push(Facet { v1: f.v1 + base, v2: f.v2 + base, v3: f.v3 + base, ..*f });
+        }
+        let colors = match (&self.colors, &other.colors) {
+            (&Some(ref a), &Some(ref b)) => Some(a.iter().chain(b.iter()).map(|c| *c).collect()),
+            _ => None,
+        };
+        let mut merged = self.with_parts(vs, fs, colors);
+        merged.header = self.header.clone().or(other.header.clone());
+        merged.byte_order = if self.is_empty() { other.byte_order } else { self.byte_order };
+        merged.name = self.name.clone().or(other.name.clone());
+        merged
+    }
+
+    // Merges vertices with bit-identical coordinates by running every facet
+    // corner through a fresh VertexMap. Vertices no facet refers to are
+    // dropped along the way.
+    pub fn deduplicate(&self) -> Mesh {
+        let mut vm = VertexMap::new();
+        let mut fs: Vec<Facet> = Vec::with_capacity(self.facets.len());
+        for f in self.facets.iter() {
+            let v1 = vm.add(self.vertices[f.v1]);
+            let v2 = vm.add(self.vertices[f.v2]);
+            let v3 = vm.add(self.vertices[f.v3]);
+            fs.push(Facet { v1: v1, v2: v2, v3: v3, ..*f });
+        }
+        self.with_parts(vm.vector(), fs, None)
+    }
+
+    // The opposite of deduplicate: every facet gets its own copy of its
+    // three corners.
+    fn unshared(&self) -> Mesh {
+        let mut vs: Vec<Vector3D> = Vec::with_capacity(self.facets.len() * 3);
+        let mut fs: Vec<Facet> = Vec::with_capacity(self.facets.len());
+        for f in self.facets.iter() {
+            let i = vs.len();
+            vs.push(self.vertices[f.v1]);
+            vs.push(self.vertices[f.v2]);
+            vs.push(self.vertices[f.v3]);
+            fs.push(Facet { v1: i, v2: i + 1, v3: i + 2, ..*f });
+        }
+        self.with_parts(vs, fs, None)
+    }
+
+    // Keeps the facets whose three corners all lie inside the box from min
+    // to max, boundary included. Facets straddling the box are dropped
+    // rather than clipped, and so are the vertices only they used.
+    pub fn crop(&self, min: Vector3D, max: Vector3D) -> Mesh {
+        let inside = |i: usize| {
+            let v = &self.vertices[i];
+            v.x >= min.x && v.y >= min.y && v.z >= min.z
+                && v.x <= max.x && v.y <= max.y && v.z <= max.z
+        };
+        let fs = self.facets.iter().filter(|f| inside(f.v1) && inside(f.v2) && inside(f.v3))
+            .map(|f| *f).collect();
+        self.with_parts(self.vertices.clone(), fs, self.colors.clone()).compact()
+    }
+
+    // Drops vertices no facet refers to, keeping the rest in their original
+    // order. Unlike deduplicate, coincident vertices are left alone.
+    pub fn compact(&self) -> Mesh {
+        let mut remap: Vec<Option<usize>> = (0..self.vertices.len()).map(|_| None).collect();
+        for f in self.facets.iter() {
+            for &i in [f.v1, f.v2, f.v3].iter() {
+                remap[i] = Some(0);
+            }
+        }
+        let mut vs: Vec<Vector3D> = Vec::new();
+        for (i, r) in remap.iter_mut().enumerate() {
+            if r.is_some() {
+                *r = Some(vs.len());
+                vs.push(self.vertices[i]);
+            }
+        }
+        let colors = self.colors.as_ref().map(|c| {
+            c.iter().zip(remap.iter()).filter(|&(_, r)| r.is_some()).map(|(c, _)| *c).collect()
+        });
+        let fs = self.facets.iter().map(|f| Facet {
+            v1: remap[f.v1].unwrap(),
+            v2: remap[f.v2].unwrap(),
+            v3: remap[f.v3].unwrap(),
+            ..*f
+        }).collect();
+        self.with_parts(vs, fs, colors)
+    }
+
+    // A copy with the vertices sorted by (x, y, z) and each facet rotated to
+    // start at its smallest vertex index, keeping its winding, and the facets
+    // then sorted by their indices. Meshes with the same facets listed in a
+    // different order come out the same.
+    pub fn canonicalize(&self) -> Mesh {
+        let key = |v: &Vector3D| (v.x, v.y, v.z);
+        let mut order: Vec<usize> = (0..self.vertices.len()).collect();
+        order.sort_by(|&a, &b| {
+            key(&self.vertices[a]).partial_cmp(&key(&self.vertices[b]))
+                .unwrap_or(::std::cmp::Ordering::Equal)
+        });
+        let mut remap: Vec<usize> = (0..order.len()).map(|_| 0).collect();
+        for (i, &old) in order.iter().enumerate() {
+            remap[old] = i;
+        }
+        let mut fs: Vec<Facet> = self.facets.iter().map(|f| {
+            let (a, b, c) = (remap[f.v1], remap[f.v2], remap[f.v3]);
+            let (v1, v2, v3) = if a <= b && a <= c {
+                (a, b, c)
+            } else if b <= c {
+                (b, c, a)
+            } else {
+                (c, a, b)
+            };
+            Facet { v1: v1, v2: v2, v3: v3, ..*f }
+        }).collect();
+        fs.sort_by(|x, y| (x.v1, x.v2, x.v3).cmp(&(y.v1, y.v2, y.v3)));
+        let vs = order.iter().map(|&i| self.vertices[i]).collect();
+        let colors = self.colors.as_ref().map(|c| order.iter().map(|&i| c[i]).collect());
+        self.with_parts(vs, fs, colors)
+    }
+
+    // Merges vertices lying within eps of an earlier vertex into it. Vertices
+    // are bucketed in a hash grid of eps-sized cells, so only the 27 cells
+    // around a vertex need searching. Facets that collapse because two of
+    // their corners were welded together are dropped.
+    pub fn weld_with_tolerance(&self, eps: f32) -> Mesh {
+        let cell = |v: &Vector3D| -> (i64, i64, i64) {
+            ((v.x / eps).floor() as i64, (v.y / eps).floor() as i64, (v.z / eps).floor() as i64)
+        };
+        let mut grid: HashMap<(i64, i64, i64), Vec<usize>> = HashMap::new();
+        let mut vs: Vec<Vector3D> = Vec::new();
+        let mut remap: Vec<usize> = Vec::with_capacity(self.vertices.len());
+        for v in self.vertices.iter() {
+            let (cx, cy, cz) = cell(v);
+            let mut found = None;
+            'search: for dx in -1..2 {
+                for dy in -1..2 {
+                    for dz in -1..2 {
+                        if let Some(reps) = grid.get(&(cx + dx, cy + dy, cz + dz)) {
+                            for &ri in reps.iter() {
+                                let r = &vs[ri];
+                                if (*v - *r).length() <= eps {
+                                    found = Some(ri);
+                                    break 'search;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            let idx = match found {
+                Some(ri) => ri,
+                None => {
+                    let idx = vs.len();
+                    vs.push(*v);
+                    grid.entry((cx, cy, cz)).get().unwrap_or_else(|e| e.insert(Vec::new()))
+                        .push(idx);
+                    idx
+                }
+            };
+            remap.push(idx);
+        }
+        let fs = self.facets.iter()
+            .map(|f| Facet { v1: remap[f.v1], v2: remap[f.v2], v3: remap[f.v3], ..*f })
+            .filter(|f| f.v1 != f.v2 && f.v2 != f.v3 && f.v3 != f.v1)
+            .collect();
+        self.with_parts(vs, fs, None)
+    }
+
+    // Reduces the facet count toward target_ratio of the original by
+    // collapsing the shortest edges to their midpoints. Stops when the
+    // target is reached or a pass finds nothing to collapse.
+    pub fn decimate(&self, target_ratio: f32) -> Mesh {
+        let target = (self.facets.len() as f32 * target_ratio).ceil() as usize;
+        self.collapse_edges(|_, remaining| remaining <= target)
+    }
+
+    // Collapses every edge shorter than eps to its midpoint, as far as that
+    // can be done without flipping a facet, to clean up the tiny features
+    // CAD exports can leave.
+    pub fn collapse_short_edges(&self, eps: f32) -> Mesh {
+        self.collapse_edges(|len, _| len >= eps)
+    }
+
+    // Collapses edges to their midpoints, shortest first, until done says
+    // to stop given the length of the next edge and the facets remaining.
+    // A collapse is skipped if it would flip or flatten any facet around it,
+    // and facets left with a repeated corner are removed. Within a pass each
+    // vertex takes part in at most one collapse, so edge lengths and facet
+    // neighbourhoods can be computed once per pass. Passes repeat until
+    // one collapses nothing.
+    fn collapse_edges<F: Fn(f32, usize) -> bool>(&self, done: F) -> Mesh {
+        let mut vs = self.vertices.clone();
+        let mut fs = self.facets_without_normals();
+        let cross = |a: &Vector3D, b: &Vector3D, c: &Vector3D| {
+            let u = *b - *a;
+            let v = *c - *a;
+            u.cross(&v)
+        };
+        loop {
+            let mut around: Vec<Vec<usize>> = (0..vs.len()).map(|_| Vec::new()).collect();
+            let mut edges: Vec<(usize, usize)> = Vec::with_capacity(fs.len() * 3);
+            for (fi, f) in fs.iter().enumerate() {
+                for &(a, b) in [(f.v1, f.v2), (f.v2, f.v3), (f.v3, f.v1)].iter() {
+                    around[a].push(fi);
+                    edges.push(if a < b { (a, b) } else { (b, a) });
+                }
+            }
+            edges.sort();
+            edges.dedup();
+            let mut by_length: Vec<(f32, usize, usize)> = edges.iter().map(|&(a, b)| {
+                ((vs[a] - vs[b]).length(), a, b)
+            }).collect();
+            by_length.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap_or(::std::cmp::Ordering::Equal));
+
+            let mut moved: Vec<bool> = (0..vs.len()).map(|_| false).collect();
+            let mut dead: Vec<bool> = (0..fs.len()).map(|_| false).collect();
+            let mut remaining = fs.len();
+            let mut collapsed = false;
+            for &(len, a, b) in by_length.iter() {
+                if done(len, remaining) {
+                    break;
+                }
+                if moved[a] || moved[b] {
+                    continue;
+                }
+                let m = Vector3D {
+                    x: (vs[a].x + vs[b].x) / 2.0,
+                    y: (vs[a].y + vs[b].y) / 2.0,
+                    z: (vs[a].z + vs[b].z) / 2.0,
+                };
+                let flips = {
+                    let at = |i: usize| if i == a || i == b { m } else { vs[i] };
+                    around[a].iter().chain(around[b].iter()).any(|&fi| {
+                        let f = &fs[fi];
+                        let corners = [f.v1, f.v2, f.v3];
+                        if corners.contains(&a) && corners.contains(&b) {
+                            return false;
+                        }
+                        let before = cross(&vs[f.v1], &vs[f.v2], &vs[f.v3]);
+                        let after = cross(&at(f.v1), &at(f.v2), &at(f.v3));
+                        before.dot(&after) <= 0.0
+                    })
+                };
+                if flips {
+                    continue;
+                }
+                vs[a] = m;
+                for &fi in around[b].iter() {
+                    if dead[fi] {
+                        continue;
+                    }
+                    let f = &mut fs[fi];
+                    if f.v1 == b { f.v1 = a; }
+                    if f.v2 == b { f.v2 = a; }
+                    if f.v3 == b { f.v3 = a; }
+                    if f.v1 == f.v2 || f.v2 == f.v3 || f.v3 == f.v1 {
+                        dead[fi] = true;
+                        remaining -= 1;
+                    }
+                }
+                moved[a] = true;
+                moved[b] = true;
+                collapsed = true;
+            }
+            fs = fs.iter().zip(dead.iter()).filter(|&(_, &d)| !d).map(|(f, _)| *f).collect();
+            if !collapsed {
+                break;
+            }
+        }
+        // Collapsed-away vertices are no longer referenced; compact drops
+        // them and renumbers the rest without merging any, so no facet can
+        // end up with a repeated corner.
+        self.with_parts(vs, fs, None).compact()
+    }
+
+    // Splits every facet into four by joining its edge midpoints, keeping
+    // the winding. Midpoints go through the same VertexMap as the original
+    // vertices, and since (a + b) / 2 is bit-identical to (b + a) / 2 the
+    // two facets sharing an edge share its midpoint, so no cracks open up.
+    pub fn subdivide(&self) -> Mesh {
+        let mut vm = VertexMap::new();
+        let mut fs: Vec<Facet> = Vec::with_capacity(self.facets.len() * 4);
+        let mid = |a: &Vector3D, b: &Vector3D| Vector3D {
+            x: (a.x + b.x) / 2.0,
+            y: (a.y + b.y) / 2.0,
+            z: (a.z + b.z) / 2.0,
+        };
+        for f in self.facets.iter() {
+            let (a, b, c) = (&self.vertices[f.v1], &self.vertices[f.v2], &self.vertices[f.v3]);
+            let ai = vm.add(*a);
+            let bi = vm.add(*b);
+            let ci = vm.add(*c);
+            let abi = vm.add(mid(a, b));
+            let bci = vm.add(mid(b, c));
+            let cai = vm.add(mid(c, a));
+            fs.push(Facet { v1: ai, v2: abi, v3: cai, ..*f });
+            fs.push(Facet { v1: abi, v2: bi, v3: bci, ..*f });
+            fs.push(Facet { v1: cai, v2: bci, v3: ci, ..*f });
+            fs.push(Facet { v1: abi, v2: bci, v3: cai, ..*f });
+        }
+        self.with_parts(vm.vector(), fs, None)
+    }
+
+    // Laplacian smoothing: each iteration moves every vertex lambda of the
+    // way toward the average of the vertices it shares an edge with. With
+    // pin_boundary, vertices on open or non-manifold edges stay put so the
+    // outline of an open mesh doesn't shrink.
+    pub fn smooth(&self, iterations: usize, lambda: f32, pin_boundary: bool) -> Mesh {
+        let mut neighbours: Vec<Vec<usize>> = (0..self.vertices.len()).map(|_| Vec::new()).collect();
+        for f in self.facets.iter() {
+            for &(a, b) in [(f.v1, f.v2), (f.v2, f.v3), (f.v3, f.v1)].iter() {
+                neighbours[a].push(b);
+                neighbours[b].push(a);
+            }
+        }
+        for n in neighbours.iter_mut() {
+            n.sort();
+            n.dedup();
+        }
+        let mut pinned: Vec<bool> = (0..self.vertices.len()).map(|_| false).collect();
+        if pin_boundary {
+            for &(a, b) in self.non_manifold_edges().iter() {
+                pinned[a] = true;
+                pinned[b] = true;
+            }
+        }
+        let mut vs = self.vertices.clone();
+        for _ in 0..iterations {
+            let next: Vec<Vector3D> = vs.iter().enumerate().map(|(i, v)| {
+                let n = &neighbours[i];
+                if pinned[i] || n.is_empty() {
+                    return *v;
+                }
+                let mut avg = Vector3D { x: 0.0, y: 0.0, z: 0.0 };
+                for &j in n.iter() {
+                    avg.x += vs[j].x;
+                    avg.y += vs[j].y;
+                    avg.z += vs[j].z;
+                }
+                let count = n.len() as f32;
+                Vector3D {
+                    x: v.x + lambda * (avg.x / count - v.x),
+                    y: v.y + lambda * (avg.y / count - v.y),
+                    z: v.z + lambda * (avg.z / count - v.z),
+                }
+            }).collect();
+            vs = next;
+        }
+        self.with_parts(vs, self.facets_without_normals(), self.colors.clone())
+    }
+
+    // Rounds every coordinate to the nearest multiple of grid so that
+    // vertices differing only by rounding become identical and can then be
+    // merged by deduplicate. -0.0 is folded into 0.0 for the same reason.
+    pub fn snap(&self, grid: f32) -> Mesh {
+        self.map_vertices(|v| Vector3D {
+            x: (v.x / grid).round() * grid + 0.0,
+            y: (v.y / grid).round() * grid + 0.0,
+            z: (v.z / grid).round() * grid + 0.0,
+        })
+    }
+
+    // Moves the mesh straight up or down so its lowest point rests on Z=0,
+    // the build plate. An empty mesh is returned unchanged.
+    pub fn drop_to_plate(&self) -> Mesh {
+        match self.bounding_box() {
+            Some((min, _)) => self.translate(Vector3D { x: 0.0, y: 0.0, z: -min.z }),
+            None => self.clone(),
+        }
+    }
+
+    // Replicates the mesh nx*ny*nz times in a grid, leaving spacing between
+    // the bounding boxes of neighbouring copies. An empty mesh is returned
+    // unchanged.
+    pub fn array(&self, nx: usize, ny: usize, nz: usize, spacing: f32) -> Mesh {
+        let (min, max) = match self.bounding_box() {
+            Some(bb) => bb,
+            None => return self.clone(),
+        };
+        let step = Vector3D {
+            x: max.x - min.x + spacing,
+            y: max.y - min.y + spacing,
+            z: max.z - min.z + spacing,
+        };
+        self.array_pitch(nx, ny, nz, step)
+    }
+
+    // Replicates the mesh nx*ny*nz times in a grid, copy (i, j, k) being
+    // offset by (i*pitch.x, j*pitch.y, k*pitch.z). Each copy's facets are
+    // renumbered to its own vertices.
+    pub fn array_pitch(&self, nx: usize, ny: usize, nz: usize, pitch: Vector3D) -> Mesh {
+        let copies = nx * ny * nz;
+        let mut vs: Vec<Vector3D> = Vec::with_capacity(self.vertices.len() * copies);
+        let mut fs: Vec<Facet> = Vec::with_capacity(self.facets.len() * copies);
+        for k in 0..nz {
+            for j in 0..ny {
+                for i in 0..nx {
+                    let base = vs.len();
+                    let offset = Vector3D {
+                        x: pitch.x * i as f32,
+                        y: pitch.y * j as f32,
+                        z: pitch.z * k as f32,
+                    };
+                    for v in self.vertices.iter() {
+                        vs.push(*v + offset);
+                    }
+                    for f in self.facets.iter() {
+                        fs.push(Facet { v1: f.v1 + base, v2: f.v2 + base, v3: f.v3 + base, ..*f });
+                    }
+                }
+            }
+        }
+        self.with_parts(vs, fs, None)
+    }
+
+    // The convex hull of the vertices, built incrementally: start from a
+    // tetrahedron of far-apart points, then for each point outside the hull
+    // so far remove the facets it can see and fan new ones from it to the
+    // horizon they leave behind. Work is done in f64 with a tolerance
+    // scaled to the size of the mesh. Points that are all coplanar or
+    // collinear have no solid hull and give an empty mesh.
+    pub fn convex_hull(&self) -> Mesh {
+        fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+            [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+        }
+        fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+            a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+        }
+        fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+            [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+        }
+        // Signed distance of p above the plane of facet f.
+        fn height(ps: &[[f64; 3]], f: [usize; 3], p: usize) -> f64 {
+            let n = cross(sub(ps[f[1]], ps[f[0]]), sub(ps[f[2]], ps[f[0]]));
+            let len = dot(n, n).sqrt();
+            if len == 0.0 { 0.0 } else { dot(n, sub(ps[p], ps[f[0]])) / len }
+        }
+
+        let ps: Vec<[f64; 3]> = self.vertices.iter()
+            .map(|v| [v.x as f64, v.y as f64, v.z as f64])
+            .collect();
+        if ps.len() < 4 {
+            return Mesh::new();
+        }
+        let farthest = |dist: &Fn(usize) -> f64| -> (usize, f64) {
+            (0..ps.len()).fold((0, 0.0), |(bi, bd), i| {
+                let d = dist(i);
+                if d > bd { (i, d) } else { (bi, bd) }
+            })
+        };
+        let a = (0..ps.len()).fold(0, |lo, i| if ps[i][0] < ps[lo][0] { i } else { lo });
+        let (b, ab) = farthest(&|i| dot(sub(ps[i], ps[a]), sub(ps[i], ps[a])).sqrt());
+        let eps = ab * 1e-6;
+        if ab == 0.0 {
+            return Mesh::new();
+        }
+        let (c, off_line) = farthest(&|i| {
+            let n = cross(sub(ps[b], ps[a]), sub(ps[i], ps[a]));
+            dot(n, n).sqrt() / ab
+        });
+        if off_line <= eps {
+            return Mesh::new();
+        }
+        let (d, off_plane) = farthest(&|i| height(ps.as_slice(), [a, b, c], i).abs());
+        if off_plane <= eps {
+            return Mesh::new();
+        }
+
+        // Each facet of the tetrahedron is wound to face away from the
+        // corner opposite it.
+        let mut faces: Vec<[usize; 3]> = [(a, b, c, d), (a, b, d, c), (b, c, d, a), (c, a, d, b)]
+            .iter()
+            .map(|&(i, j, k, opposite)| {
+                if height(ps.as_slice(), [i, j, k], opposite) > 0.0 { [i, k, j] } else { [i, j, k] }
+            })
+            .collect();
+
+        for p in 0..ps.len() {
+            let visible: Vec<bool> = faces.iter()
+                .map(|&f| height(ps.as_slice(), f, p) > eps)
+                .collect();
+            if !visible.iter().any(|&v| v) {
+                continue;
+            }
+            // The horizon is made of the edges of visible facets whose
+            // other facet is not visible, i.e. whose reverse isn't listed.
+            let mut edges: HashSet<(usize, usize)> = HashSet::new();
+            let mut next: Vec<[usize; 3]> = Vec::with_capacity(faces.len());
+            for (f, &v) in faces.iter().zip(visible.iter()) {
+                if v {
+                    edges.insert((f[0], f[1]));
+                    edges.insert((f[1], f[2]));
+                    edges.insert((f[2], f[0]));
+                } else {
+                    next.push(*f);
+                }
+            }
+            for &(u, w) in edges.iter() {
+                if !edges.contains(&(w, u)) {
+                    next.push([u, w, p]);
+                }
+            }
+            faces = next;
+        }
+
+        let fs = faces.iter().map(|f| Facet::new(f[0], f[1], f[2])).collect();
+        self.with_parts(self.vertices.clone(), fs, self.colors.clone()).compact()
+    }
+
+    pub fn read<R: Reader>(r: &mut BufferedReader<R>) -> Result<Mesh, MeshError> {
+        Mesh::read_with(r, true, ByteOrder::Little)
+    }
+
+    // Reads without merging shared vertices, so the mesh has three vertices
+    // per facet. Binary STL skips the VertexMap hashing altogether, which is
+    // most of the cost of reading a big file.
+    pub fn read_no_dedup<R: Reader>(r: &mut BufferedReader<R>) -> Result<Mesh, MeshError> {
+        Mesh::read_with(r, false, ByteOrder::Little)
+    }
+
+    // Reads with every option spelled out: dedup as for read_no_dedup, and
+    // the byte order binary STL is taken to be in. ASCII STL ignores it.
+    // The solids of a multi-solid ASCII STL are merged into one mesh.
+    pub fn read_with<R: Reader>(r: &mut BufferedReader<R>, dedup: bool, order: ByteOrder)
+            -> Result<Mesh, MeshError> {
+        Ok(Mesh::merge_solids(try!(Mesh::read_solids(r, dedup, order))))
+    }
+
+    fn merge_solids(mut solids: Vec<Mesh>) -> Mesh {
+        if solids.len() == 1 {
+            return solids.pop().unwrap();
+        }
+        solids.iter().fold(Mesh::new(), |all, m| all.merge(m))
+    }
+
+    // Like read_with, but keeps each solid of an ASCII STL as a separate
+    // mesh, named after its solid line. Their vertices are deduplicated
+    // within each solid but not across them. Binary STL, and ASCII STL with
+    // a single solid, give a list of one; an ASCII file with no facets gives
+    // an empty list.
+    pub fn read_solids<R: Reader>(r: &mut BufferedReader<R>, dedup: bool, order: ByteOrder)
+            -> Result<Vec<Mesh>, MeshError> {
+        Mesh::read_solids_with(r, dedup, order, false).map(|(solids, _)| solids)
+    }
+
+    // Like read_solids, but a file that is cut short or garbled partway
+    // through gives the facets read before the damage, along with what was
+    // left out, rather than an error. A file too short to tell its format,
+    // or a binary one without its facet count, is still an error.
+    pub fn read_solids_recover<R: Reader>(r: &mut BufferedReader<R>, dedup: bool,
+                                          order: ByteOrder)
+            -> Result<(Vec<Mesh>, Option<Shortfall>), MeshError> {
+        Mesh::read_solids_with(r, dedup, order, true)
+    }
+
+    fn read_solids_with<R: Reader>(r: &mut BufferedReader<R>, dedup: bool, order: ByteOrder,
+                                   recover: bool)
+            -> Result<(Vec<Mesh>, Option<Shortfall>), MeshError> {
+
+        // Binary headers are arbitrary bytes, so the format is decided on the
+        // raw bytes and the text is only decoded (lossily) for display and
+        // for the ASCII parser.
+        let mut buf = [0u8; 80];
+        if r.read_at_least(buf.len(), &mut buf).is_err() {
+            return Err(MeshError::TruncatedHeader);
+        }
+        let header = String::from_utf8_lossy(&buf).into_owned();
+        diag!("Header: \"{}\"", header);
+
+        if !buf.starts_with(b"solid ") {
+            diag!("Is binary STL");
+            let (mut mesh, shortfall) = try!(Mesh::read_binary(r, dedup, order, recover));
+            mesh.header = Some(buf.to_vec());
+            return Ok((vec![mesh], shortfall));
+        }
+
+        // Plenty of binary STLs start their header with "solid" too. The
+        // input may be a pipe, so rather than seeking the rest is read into
+        // memory and checked: if its size is exactly what the facet count
+        // calls for it's binary, otherwise it's ASCII if it has any ASCII
+        // STL keywords in it.
+        let rest = match r.read_to_end() {
+            Ok(d) => d,
+            Err(e) => return Err(MeshError::Malformed(format!("read error: {}", e))),
+        };
+        if Mesh::looks_binary(&buf, rest.as_slice(), order) {
+            diag!("Is binary STL with a \"solid\" header");
+            let (mut mesh, shortfall) =
+                try!(Mesh::read_binary(&mut MemReader::new(rest), dedup, order, recover));
+            mesh.header = Some(buf.to_vec());
+            Ok((vec![mesh], shortfall))
+        } else {
+            diag!("Is ASCII STL");
+            let (solids, shortfall) = try!(Mesh::read_ascii(header.as_slice(),
+                &mut BufferedReader::new(MemReader::new(rest)), recover)
+                .map_err(MeshError::Malformed));
+            let solids = if dedup { solids } else { solids.iter().map(|m| m.unshared()).collect() };
+            Ok((solids, shortfall))
+        }
+    }
+
+    // Counts the facets of an STL without building the mesh. Binary STL is
+    // read only as far as the facet count after the header, unless the
+    // header starts with "solid" and the rest has to be looked at to tell
+    // it from ASCII as in read_solids. For ASCII the "facet normal" lines
+    // are counted.
+    pub fn count_facets<R: Reader>(r: &mut BufferedReader<R>, order: ByteOrder)
+            -> Result<usize, MeshError> {
+        let mut buf = [0u8; 80];
+        if r.read_at_least(buf.len(), &mut buf).is_err() {
+            return Err(MeshError::TruncatedHeader);
+        }
+        if !buf.starts_with(b"solid ") {
+            return match binary_facet_count(r, order) {
+                Ok(c) => Ok(c as usize),
+                Err(_) => Err(MeshError::TruncatedFacetCount),
+            };
+        }
+        let rest = match r.read_to_end() {
+            Ok(d) => d,
+            Err(e) => return Err(MeshError::Malformed(format!("read error: {}", e))),
+        };
+        if Mesh::looks_binary(&buf, rest.as_slice(), order) {
+            return match binary_facet_count(&mut MemReader::new(rest), order) {
+                Ok(c) => Ok(c as usize),
+                Err(_) => Err(MeshError::TruncatedFacetCount),
+            };
+        }
+        // The header is text too, and the first facet often starts in it.
+        let mut text = buf.to_vec();
+        text.push_all(rest.as_slice());
+        let word: &[u8] = b"facet normal";
+        Ok(text.windows(word.len()).filter(|&w| w == word).count())
+    }
+
+    // Whether an STL whose header starts with "solid" is binary after all,
+    // given the header and the rest of the file: it is if its size is
+    // exactly what the facet count calls for, or if it has no ASCII STL
+    // keywords in it.
+    fn looks_binary(header: &[u8], rest: &[u8], order: ByteOrder) -> bool {
+        let contains = |data: &[u8], word: &[u8]| data.windows(word.len()).any(|w| w == word);
+        let sized_as_binary = rest.len() >= 4 && {
+            let b: Vec<u64> = match order {
+                ByteOrder::Little => rest[..4].iter().map(|&b| b as u64).collect(),
+                ByteOrder::Big => rest[..4].iter().rev().map(|&b| b as u64).collect(),
+            };
+            let count = b[0] | b[1] << 8 | b[2] << 16 | b[3] << 24;
+            rest.len() as u64 == 4 + 50 * count
+        };
+        let has_keywords = contains(rest, b"facet normal")
+            || contains(header, b"endsolid") || contains(rest, b"endsolid");
+        sized_as_binary || !has_keywords
+    }
+
+    // The 80 header bytes have already been consumed by read, so they are
+    // passed in and parsed ahead of the rest of the stream. Each solid in
+    // the file comes back as a mesh of its own.
+    fn read_ascii<R: Reader>(header: &str, r: &mut BufferedReader<R>, recover: bool)
+            -> Result<(Vec<Mesh>, Option<Shortfall>), String> {
+        //solid vcg
+        //  facet normal 7.733874e-001 -3.151335e-002 6.331499e-001
+        //    outer loop
+        //      vertex  2.137833e+001 5.385936e+001 1.350253e+001
+        //      vertex  2.341920e+001 4.700068e+001 1.066826e+001
+        //      vertex  2.859041e+001 5.985682e+001 4.991545e+000
+        //    endloop
+        //  endfacet
+        //endsolid vcg
+        let mut parser = AsciiStlParser::new();
+        let mut shortfall = None;
+        if let Err(e) = parser.read(header, r) {
+            if !recover {
+                return Err(e);
+            }
+            shortfall = Some(parser.abandon(e));
+        }
+        let (solids, at_end) = try!(parser.finish(recover));
+        Ok((solids, shortfall.or(at_end)))
+    }
+
+    fn read_binary<R: Reader>(r: &mut R, dedup: bool, order: ByteOrder, recover: bool)
+            -> Result<(Mesh, Option<Shortfall>), MeshError> {
+        let facet_count = match binary_facet_count(r, order) {
+            Ok(c) => { diag!("Facets: {}", c); c},
+            Err(_) => return Err(MeshError::TruncatedFacetCount),
+        };
+
+        // A closed mesh has about half as many vertices as facets, so sizing
+        // for one per facet avoids rehashing without overshooting by much.
+        let mut facets: Vec<StlFacet> = Vec::with_capacity(facet_count as usize);
+        let mut vertices = VertexMap::with_capacity(if dedup { facet_count as usize } else { 0 });
+        diag!("Collections ready");
+
+        // The counter is only redrawn when the whole percentage changes, so
+        // it costs at most a hundred writes however big the file is.
+        let show_progress = ::progress() && facet_count > 0;
+        let mut shown = 0;
+        let mut shortfall = None;
+        for fi in 0..facet_count {
+            let f = match StlFacet::read(r, order) {
+                Ok(f) => f,
+                Err(_) if recover => {
+                    shortfall = Some(Shortfall {
+                        reason: MeshError::TruncatedFacetData(fi as usize),
+                        kept: fi as usize,
+                        declared: Some(facet_count as usize),
+                    });
+                    break;
+                }
+                Err(_) => return Err(MeshError::TruncatedFacetData(fi as usize)),
+            };
+            if dedup {
+                vertices.add(f.v1);
+                vertices.add(f.v2);
+                vertices.add(f.v3);
+            }
+            facets.push(f);
+            if show_progress {
+                let percent = (fi as u64 + 1) * 100 / facet_count as u64;
+                if percent != shown {
+                    shown = percent;
+                    let _ = write!(&mut ::std::old_io::stderr(), "\rReading: {}%", percent);
+                }
+            }
+        }
+        if show_progress {
+            let _ = writeln!(&mut ::std::old_io::stderr(), "");
+        }
+        let mut mesh = if dedup {
+            diag!("Vertices: {}", vertices.len());
+            Mesh::new_from_stl(&facets, &vertices)
+        } else {
+            Mesh::new_from_stl_unshared(&facets)
+        };
+        mesh.byte_order = order;
+        Ok((mesh, shortfall))
+    }
+}
+
+// The facet count that follows the header of a binary STL.
+fn binary_facet_count<R: Reader>(r: &mut R, order: ByteOrder) -> IoResult<u32> {
+    match order {
+        ByteOrder::Little => r.read_le_u32(),
+        ByteOrder::Big => r.read_be_u32(),
+    }
+}
+
+// Area of a triangle, half the magnitude of the cross product of two of its
+// edges.
+fn triangle_area(t: &[Vector3D; 3]) -> f32 {
+    (t[1] - t[0]).cross(&(t[2] - t[0])).length() / 2.0
+}
+
+// Iterator over the corners of a mesh's facets; see Mesh::triangles.
+pub struct Triangles<'a> {
+    vertices: &'a [Vector3D],
+    facets: slice::Iter<'a, Facet>,
+}
+
+impl<'a> Iterator for Triangles<'a> {
+    type Item = [Vector3D; 3];
+
+    fn next(&mut self) -> Option<[Vector3D; 3]> {
+        let vs = self.vertices;
+        self.facets.next().map(|f| [vs[f.v1], vs[f.v2], vs[f.v3]])
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.facets.size_hint()
+    }
+}
+
+// Written at the start of the 80-byte binary STL header, which is padded out
+// with zeros. It must not begin with "solid" or readers will take the file
+// for ASCII.
+static STL_HEADER: &'static str = "Binary STL written by meshman";
+
+pub struct StlFile;
+
+impl StlFile {
+    // Gzip-compressed STL is recognised by its magic bytes, whatever the
+    // file is called, and unpacked in memory before being parsed.
+    pub fn read<R: Reader>(r: &mut BufferedReader<R>) -> Result<Mesh, MeshError> {
+        StlFile::read_with(r, true, ByteOrder::Little)
+    }
+
+    // Reads triangle soup; see Mesh::read_no_dedup.
+    pub fn read_no_dedup<R: Reader>(r: &mut BufferedReader<R>) -> Result<Mesh, MeshError> {
+        StlFile::read_with(r, false, ByteOrder::Little)
+    }
+
+    // See Mesh::read_with.
+    pub fn read_with<R: Reader>(r: &mut BufferedReader<R>, dedup: bool, order: ByteOrder)
+            -> Result<Mesh, MeshError> {
+        Ok(Mesh::merge_solids(try!(StlFile::read_solids(r, dedup, order))))
+    }
+
+    // See Mesh::read_solids.
+    pub fn read_solids<R: Reader>(r: &mut BufferedReader<R>, dedup: bool, order: ByteOrder)
+            -> Result<Vec<Mesh>, MeshError> {
+        StlFile::read_solids_with(r, dedup, order, false).map(|(solids, _)| solids)
+    }
+
+    // See Mesh::read_solids_recover. A gzip stream that is itself cut short
+    // can't be unpacked, so that is still an error.
+    pub fn read_solids_recover<R: Reader>(r: &mut BufferedReader<R>, dedup: bool,
+                                          order: ByteOrder)
+            -> Result<(Vec<Mesh>, Option<Shortfall>), MeshError> {
+        StlFile::read_solids_with(r, dedup, order, true)
+    }
+
+    fn read_solids_with<R: Reader>(r: &mut BufferedReader<R>, dedup: bool, order: ByteOrder,
+                                   recover: bool)
+            -> Result<(Vec<Mesh>, Option<Shortfall>), MeshError> {
+        let compressed = match r.fill_buf() {
+            Ok(buf) => inflate::is_gzip(buf),
+            Err(_) => false,
+        };
+        if !compressed {
+            return Mesh::read_solids_with(r, dedup, order, recover);
+        }
+        let data = match r.read_to_end() {
+            Ok(d) => d,
+            Err(e) => return Err(MeshError::Malformed(format!("read error: {}", e))),
+        };
+        let data = try!(inflate::gunzip(data.as_slice()).map_err(MeshError::Malformed));
+        Mesh::read_solids_with(&mut BufferedReader::new(MemReader::new(data)), dedup, order,
+                               recover)
+    }
+
+    // See Mesh::count_facets. Gzip-compressed STL has to be unpacked first,
+    // so it saves only the parsing.
+    pub fn count_facets<R: Reader>(r: &mut BufferedReader<R>, order: ByteOrder)
+            -> Result<usize, MeshError> {
+        let compressed = match r.fill_buf() {
+            Ok(buf) => inflate::is_gzip(buf),
+            Err(_) => false,
+        };
+        if !compressed {
+            return Mesh::count_facets(r, order);
+        }
+        let data = match r.read_to_end() {
+            Ok(d) => d,
+            Err(e) => return Err(MeshError::Malformed(format!("read error: {}", e))),
+        };
+        let data = try!(inflate::gunzip(data.as_slice()).map_err(MeshError::Malformed));
+        Mesh::count_facets(&mut BufferedReader::new(MemReader::new(data)), order)
+    }
+
+    pub fn write_binary_file(mesh: &Mesh, path: &str, recompute_normals: bool,
+                             preserve_header: bool) -> IoResult<()> {
+        let file = try!(File::create(&Path::new(path)));
+        StlFile::write_binary(mesh, &mut BufferedWriter::new(file), recompute_normals,
+                              preserve_header)
+    }
+
+    // Streams the mesh out one facet at a time so nothing beyond the mesh
+    // itself is held in memory. Each facet's stored normal is written back
+    // if it has one, and a zero normal, which readers derive from the
+    // winding, if not. The input may have had them wrong (see
+    // normal_mismatches); recompute_normals writes normals worked out from
+    // the winding instead. Each facet's attribute word is written back as it was read, and so is the byte
+    // order. The header is meshman's own unless preserve_header is set and
+    // the mesh kept one; a kept header starting with "solid" has that word
+    // blanked out so other readers don't take the file for ASCII.
+    pub fn write_binary<W: Writer>(mesh: &Mesh, w: &mut W, recompute_normals: bool,
+                                   preserve_header: bool) -> IoResult<()> {
+        let big = mesh.byte_order == ByteOrder::Big;
+        let mut header = [0u8; 80];
+        let source = match mesh.header() {
+            Some(h) if preserve_header => h,
+            _ => STL_HEADER.as_bytes(),
+        };
+        for (d, &b) in header.iter_mut().zip(source.iter()) {
+            *d = b;
+        }
+        if header.starts_with(b"solid") {
+            for b in header[..5].iter_mut() {
+                *b = b' ';
+            }
+        }
+        try!(w.write_all(&header));
+        let count = mesh.facets.len() as u32;
+        try!(if big { w.write_be_u32(count) } else { w.write_le_u32(count) });
+        for f in mesh.facets.iter() {
+            let n = if recompute_normals {
+                mesh.facet_normal(f)
+            } else {
+                f.normal.unwrap_or(Vector3D::new(0.0, 0.0, 0.0))
+            };
+            let vs = [n, mesh.vertices[f.v1], mesh.vertices[f.v2], mesh.vertices[f.v3]];
+            for v in vs.iter() {
+                for &c in [v.x, v.y, v.z].iter() {
+                    try!(if big { w.write_be_f32(c) } else { w.write_le_f32(c) });
+                }
+            }
+            try!(if big { w.write_be_u16(f.abc) } else { w.write_le_u16(f.abc) });
+        }
+        w.flush()
+    }
+
+    pub fn write_gzip_file(mesh: &Mesh, path: &str, recompute_normals: bool,
+                           preserve_header: bool) -> IoResult<()> {
+        let file = try!(File::create(&Path::new(path)));
+        StlFile::write_gzip(mesh, &mut BufferedWriter::new(file), recompute_normals,
+                            preserve_header)
+    }
+
+    // Binary STL compressed with gzip. The compressor works on a complete
+    // buffer, so unlike write_binary the whole file is built in memory.
+    pub fn write_gzip<W: Writer>(mesh: &Mesh, w: &mut W, recompute_normals: bool,
+                                 preserve_header: bool) -> IoResult<()> {
+        let mut stl = MemWriter::new();
+        try!(StlFile::write_binary(mesh, &mut stl, recompute_normals, preserve_header));
+        try!(w.write_all(deflate::gzip(stl.get_ref()).as_slice()));
+        w.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::Float;
+    use std::old_io::{BufferedReader, MemReader, MemWriter};
+    use std::time::Duration;
+
+    fn v(x: f32, y: f32, z: f32) -> Vector3D {
+        Vector3D::new(x, y, z)
+    }
+
+    fn near(a: Vector3D, b: Vector3D) -> bool {
+        a.near(&b, 1e-5)
+    }
+
+    fn triangle() -> Mesh {
+        Mesh::new_from_parts(vec![v(1.0, 2.0, 3.0), v(4.0, 2.0, 3.0), v(1.0, 6.0, 5.0)],
+                             vec![Facet::new(0, 1, 2)])
+    }
+
+    #[test]
+    fn move_to_min_puts_min_corner_at_origin() {
+        let moved = triangle().move_to(BoundsPoint::Min, v(0.0, 0.0, 0.0));
+        assert!(near(moved.bounds_point(BoundsPoint::Min).unwrap(), v(0.0, 0.0, 0.0)));
+        assert!(near(moved.bounds_point(BoundsPoint::Max).unwrap(), v(3.0, 4.0, 2.0)));
+    }
+
+    #[test]
+    fn move_to_max_puts_max_corner_at_origin() {
+        let moved = triangle().move_to(BoundsPoint::Max, v(0.0, 0.0, 0.0));
+        assert!(near(moved.bounds_point(BoundsPoint::Max).unwrap(), v(0.0, 0.0, 0.0)));
+        assert!(near(moved.bounds_point(BoundsPoint::Min).unwrap(), v(-3.0, -4.0, -2.0)));
+    }
+
+    #[test]
+    fn move_to_center_puts_center_at_origin() {
+        let moved = triangle().move_to(BoundsPoint::Center, v(0.0, 0.0, 0.0));
+        assert!(near(moved.bounds_point(BoundsPoint::Center).unwrap(), v(0.0, 0.0, 0.0)));
+        assert!(near(moved.bounds_point(BoundsPoint::Min).unwrap(), v(-1.5, -2.0, -1.0)));
+    }
+
+    #[test]
+    fn array_makes_offset_copies() {
+        let cube = Mesh::cube(1.0);
+        let grid = cube.array(2, 2, 1, 1.0);
+        assert_eq!(grid.vertex_count(), 4 * cube.vertex_count());
+        assert_eq!(grid.facet_count(), 4 * cube.facet_count());
+        let n = cube.vertex_count();
+        let offsets = [v(0.0, 0.0, 0.0), v(2.0, 0.0, 0.0), v(0.0, 2.0, 0.0), v(2.0, 2.0, 0.0)];
+        for (copy, &offset) in offsets.iter().enumerate() {
+            for (i, &p) in cube.vertices().iter().enumerate() {
+                assert!(near(grid.vertices()[copy * n + i], p + offset));
+            }
+            for (i, f) in cube.facets().iter().enumerate() {
+                let g = grid.facets()[copy * cube.facet_count() + i];
+                assert_eq!((g.v1(), g.v2(), g.v3()), (f.v1() + copy * n, f.v2() + copy * n,
+                                                      f.v3() + copy * n));
+            }
+        }
+    }
+
+    #[test]
+    fn empty_mesh_measures_are_none_or_zero() {
+        let empty = Mesh::new();
+        assert!(empty.is_empty());
+        assert_eq!(empty.bounding_box(), None);
+        assert_eq!(empty.dimensions(), None);
+        assert_eq!(empty.layer_count(0.2), None);
+        assert_eq!(empty.bounds_point(BoundsPoint::Center), None);
+        assert_eq!(empty.centroid(), None);
+        assert_eq!(empty.volume(), 0.0);
+        assert_eq!(empty.surface_area(), 0.0);
+        assert!(!empty.is_watertight());
+        assert!(empty.connected_components().is_empty());
+        assert!(empty.boundary_loops().is_empty());
+    }
+
+    #[test]
+    fn empty_mesh_operations_return_empty_meshes() {
+        let empty = Mesh::new().with_name("part");
+        let results = [
+            empty.translate(v(1.0, 2.0, 3.0)),
+            empty.scale(v(2.0, 2.0, 2.0)),
+            empty.rotate(v(0.0, 0.0, 1.0), 1.0),
+            empty.mirror(Axis::X),
+            empty.move_to(BoundsPoint::Min, v(0.0, 0.0, 0.0)),
+            empty.drop_to_plate(),
+            empty.array(2, 2, 2, 1.0),
+            empty.flip_normals(),
+            empty.orient_consistently(),
+            empty.deduplicate(),
+            empty.compact(),
+            empty.weld_with_tolerance(0.01),
+            empty.decimate(0.5),
+            empty.subdivide(),
+            empty.smooth(3, 0.5, false),
+            empty.fill_holes(),
+        ];
+        for m in results.iter() {
+            assert!(m.is_empty());
+            assert_eq!(m.vertex_count(), 0);
+            assert_eq!(m.name(), Some("part"));
+        }
+        assert!(empty.convex_hull().is_empty());
+    }
+
+    #[test]
+    fn empty_mesh_writes_a_valid_stl() {
+        let mut w = MemWriter::new();
+        StlFile::write_binary(&Mesh::new(), &mut w, false, false).unwrap();
+        assert_eq!(w.get_ref().len(), 84);
+        let mut r = BufferedReader::new(MemReader::new(w.into_inner()));
+        assert!(Mesh::read(&mut r).unwrap().is_empty());
+    }
+
+    #[test]
+    fn scale_multiplies_each_coordinate() {
+        let scaled = triangle().scale(v(2.0, 3.0, 4.0));
+        assert!(near(scaled.vertices()[0], v(2.0, 6.0, 12.0)));
+        assert!(near(scaled.vertices()[1], v(8.0, 6.0, 12.0)));
+        assert!(near(scaled.vertices()[2], v(2.0, 18.0, 20.0)));
+        assert_eq!(scaled.facets()[0], triangle().facets()[0]);
+    }
+
+    #[test]
+    fn translate_adds_the_offset() {
+        let before = triangle();
+        let moved = before.translate(v(5.0, -3.0, 2.0));
+        for (a, b) in before.vertices().iter().zip(moved.vertices().iter()) {
+            assert_eq!(*b - *a, v(5.0, -3.0, 2.0));
+        }
+    }
+
+    #[test]
+    fn unit_cube_has_area_six() {
+        let cube = Mesh::cube(1.0);
+        assert_eq!(cube.facet_count(), 12);
+        assert_eq!(cube.surface_area(), 6.0);
+    }
+
+    #[test]
+    fn cross_of_orthogonal_pair_is_perpendicular() {
+        let a = v(2.0, 0.0, 0.0);
+        let b = v(0.0, 3.0, 0.0);
+        let c = a.cross(&b);
+        assert_eq!(c, v(0.0, 0.0, 6.0));
+        assert_eq!(c.dot(&a), 0.0);
+        assert_eq!(c.dot(&b), 0.0);
+        assert_eq!(b.cross(&a), v(0.0, 0.0, -6.0));
+        assert_eq!(c.length(), 6.0);
+        assert_eq!(c.normalize(), v(0.0, 0.0, 1.0));
+        assert_eq!(v(0.0, 0.0, 0.0).normalize(), v(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn mirror_negates_the_axis_and_swaps_the_winding() {
+        let before = triangle();
+        let mirrored = before.mirror(Axis::Y);
+        for (a, b) in before.vertices().iter().zip(mirrored.vertices().iter()) {
+            assert_eq!(*b, v(a.x(), -a.y(), a.z()));
+        }
+        let (f, g) = (before.facets()[0], mirrored.facets()[0]);
+        assert_eq!((g.v1(), g.v2(), g.v3()), (f.v1(), f.v3(), f.v2()));
+        assert!(Mesh::cube(1.0).mirror(Axis::Y).volume() > 0.0);
+    }
+
+    #[test]
+    fn weld_joins_an_edge_off_by_rounding() {
+        let vs = vec![v(0.0, 0.0, 0.0), v(1.0, 0.0, 0.0), v(0.0, 1.0, 0.0),
+                      v(1.0 + 1e-6, 0.0, 0.0), v(0.0, 1.0 + 1e-6, 0.0), v(1.0, 1.0, 0.0)];
+        let mesh = Mesh::new_from_parts(vs, vec![Facet::new(0, 1, 2), Facet::new(3, 5, 4)]);
+        assert_eq!(mesh.deduplicate().vertex_count(), 6);
+        assert_eq!(mesh.boundary_loops().len(), 2);
+        let welded = mesh.weld_with_tolerance(1e-5);
+        assert_eq!(welded.vertex_count(), 4);
+        let f = welded.facets()[1];
+        assert_eq!((f.v1(), f.v2(), f.v3()), (1, 3, 2));
+        assert_eq!(welded.boundary_loops().len(), 1);
+    }
+
+    #[test]
+    fn decimate_reduces_facets_without_flipping_any() {
+        let n = 6;
+        let vs = (0..n * n).map(|i| v((i % n) as f32, (i / n) as f32, 0.0)).collect();
+        let mut fs: Vec<Facet> = Vec::new();
+        for y in 0..n - 1 {
+            for x in 0..n - 1 {
+                let i = y * n + x;
+                fs.push(Facet::new(i, i + 1, i + n + 1));
+                fs.push(Facet::new(i, i + n + 1, i + n));
+            }
+        }
+        let grid = Mesh::new_from_parts(vs, fs);
+        let decimated = grid.decimate(0.5);
+        assert!(decimated.facet_count() < grid.facet_count());
+        for t in decimated.triangles() {
+            assert!((t[1] - t[0]).cross(&(t[2] - t[0])).z() > 0.0);
+        }
+    }
+
+    #[test]
+    fn vertex_map_vector_follows_insertion_order() {
+        let points = [v(3.0, 0.0, 0.0), v(1.0, 2.0, 3.0), v(-1.0, 0.5, 0.0), v(0.0, 0.0, 9.0)];
+        let mut vm = VertexMap::new();
+        for (i, &p) in points.iter().enumerate() {
+            assert_eq!(vm.add(p), i);
+        }
+        assert_eq!(vm.add(points[1]), 1);
+        assert_eq!(vm.len(), points.len());
+        assert_eq!(vm.vector().as_slice(), points.as_slice());
+    }
+
+    #[test]
+    fn flip_normals_reverses_winding_and_stored_normal() {
+        let n = v(0.0, 0.0, 1.0);
+        let facet = Facet { normal: Some(n), abc: 0x8123, ..Facet::new(0, 1, 2) };
+        let mesh = Mesh::new_from_parts(triangle().vertices().to_vec(), vec![facet]);
+        let flipped = mesh.flip_normals();
+        let f = flipped.facets()[0];
+        assert_eq!((f.v1(), f.v2(), f.v3()), (0, 2, 1));
+        assert_eq!(f.stored_normal(), Some(-n));
+        assert_eq!(f.abc(), 0x8123);
+
+        let twice = flipped.flip_normals();
+        assert_eq!(twice.vertices(), mesh.vertices());
+        assert_eq!(twice.facets(), mesh.facets());
+    }
+
+    #[test]
+    fn drop_to_plate_rests_on_zero() {
+        for mesh in [triangle(), Mesh::sphere(2.0, 12)].iter() {
+            let dropped = mesh.drop_to_plate();
+            assert_eq!(dropped.bounding_box().unwrap().0.z(), 0.0);
+        }
+    }
+
+    #[test]
+    fn compact_drops_orphans_and_renumbers() {
+        let vs = vec![v(9.0, 9.0, 9.0), v(0.0, 0.0, 0.0), v(1.0, 0.0, 0.0), v(8.0, 8.0, 8.0),
+                      v(0.0, 1.0, 0.0)];
+        let mesh = Mesh::new_from_parts(vs, vec![Facet::new(1, 2, 4), Facet::new(4, 2, 1)]);
+        let compacted = mesh.compact();
+        assert_eq!(compacted.vertices(), [v(0.0, 0.0, 0.0), v(1.0, 0.0, 0.0), v(0.0, 1.0, 0.0)]
+                   .as_slice());
+        let corners: Vec<_> = compacted.facets().iter().map(|f| (f.v1(), f.v2(), f.v3())).collect();
+        assert_eq!(corners, vec![(0, 1, 2), (2, 1, 0)]);
+    }
+
+    fn binary_stl(mesh: &Mesh) -> Vec<u8> {
+        let mut w = MemWriter::new();
+        StlFile::write_binary(mesh, &mut w, true, false).unwrap();
+        w.into_inner()
+    }
+
+    #[test]
+    fn header_is_only_written_back_when_preserved() {
+        let mut mesh = triangle();
+        mesh.set_header(Some(b"solid looking header".to_vec()));
+        let written = |preserve: bool| -> Vec<u8> {
+            let mut w = MemWriter::new();
+            StlFile::write_binary(&mesh, &mut w, false, preserve).unwrap();
+            w.into_inner()[..80].to_vec()
+        };
+        assert!(written(false).starts_with(super::STL_HEADER.as_bytes()));
+        assert!(written(true).starts_with(b"      looking header"));
+    }
+
+    #[test]
+    fn read_no_dedup_gives_each_facet_its_own_vertices() {
+        let stl = binary_stl(&Mesh::cube(1.0));
+        let shared = Mesh::read(&mut BufferedReader::new(MemReader::new(stl.clone()))).unwrap();
+        let soup = Mesh::read_no_dedup(&mut BufferedReader::new(MemReader::new(stl))).unwrap();
+        assert_eq!(shared.facet_count(), 12);
+        assert_eq!(shared.vertex_count(), 8);
+        assert_eq!(soup.facet_count(), 12);
+        assert_eq!(soup.vertex_count(), 3 * soup.facet_count());
+        assert_eq!(soup.deduplicate().vertex_count(), shared.vertex_count());
+        for (a, b) in shared.triangles().zip(soup.triangles()) {
+            assert_eq!(a, b);
+        }
+    }
+
+    // Speed depends too much on the machine to assert on, so this only
+    // reports it; run the tests with --nocapture to see the timings.
+    #[test]
+    fn time_reads_with_and_without_dedup() {
+        let stl = binary_stl(&Mesh::sphere(10.0, 200));
+        let (mut shared, mut soup) = (None, None);
+        let (a, b) = (stl.clone(), stl);
+        let with = Duration::span(|| {
+            shared = Some(Mesh::read(&mut BufferedReader::new(MemReader::new(a))).unwrap())
+        });
+        let without = Duration::span(|| {
+            soup = Some(Mesh::read_no_dedup(&mut BufferedReader::new(MemReader::new(b))).unwrap())
+        });
+        let (shared, soup) = (shared.unwrap(), soup.unwrap());
+        let ms = |d: Duration| d.num_microseconds().unwrap_or(0) as f64 / 1000.0;
+        println!("Reading {} facets: {} ms with dedup, {} ms without",
+                 shared.facet_count(), ms(with), ms(without));
+        assert_eq!(soup.facet_count(), shared.facet_count());
+        assert_eq!(soup.vertex_count(), 3 * shared.facet_count());
+        assert_eq!(soup.deduplicate().vertex_count(), shared.vertex_count());
+    }
+
+    #[test]
+    fn diff_finds_moved_vertices() {
+        let cube = Mesh::cube(1.0);
+        let nudged = cube.translate(v(1e-6, 0.0, 0.0));
+        assert_eq!(cube.differing_vertices(&nudged, 1e-4), (vec![], vec![]));
+        assert!(cube.differing_facets(&nudged, 1e-4).is_empty());
+
+        let vs: Vec<Vector3D> = cube.vertices().iter().enumerate()
+            .map(|(i, &p)| if i == 5 { p + v(0.0, 0.0, 0.1) } else { p })
+            .collect();
+        let dented = Mesh::new_from_parts(vs, cube.facets().to_vec());
+        assert_eq!(cube.differing_vertices(&dented, 1e-4), (vec![5], vec![]));
+        assert!(!cube.differing_facets(&dented, 1e-4).is_empty());
+    }
+
+    #[test]
+    fn unordered_diff_ignores_numbering() {
+        let cube = Mesh::cube(1.0);
+        let renumbered = cube.canonicalize();
+        assert!(!cube.differing_vertices(&renumbered, 1e-4).0.is_empty());
+        assert!(cube.differing_facets(&renumbered, 1e-4).is_empty());
+    }
+
+    #[test]
+    fn components_keep_the_mesh_metadata() {
+        let vs = vec![v(0.0, 0.0, 0.0), v(1.0, 0.0, 0.0), v(0.0, 1.0, 0.0),
+                      v(5.0, 0.0, 0.0), v(6.0, 0.0, 0.0), v(5.0, 1.0, 0.0)];
+        let mut mesh = Mesh::new_from_parts(vs, vec![Facet::new(0, 1, 2), Facet::new(3, 4, 5)])
+            .with_name("pair");
+        mesh.byte_order = ByteOrder::Big;
+        mesh.set_header(Some(b"legacy exporter".to_vec()));
+        let parts = mesh.connected_components();
+        assert_eq!(parts.len(), 2);
+        for part in parts.iter() {
+            assert_eq!(part.facet_count(), 1);
+            assert_eq!(part.vertex_count(), 3);
+            assert_eq!(part.byte_order, ByteOrder::Big);
+            assert_eq!(part.name(), Some("pair"));
+            assert_eq!(part.header(), Some(b"legacy exporter".as_slice()));
+        }
+    }
+
+    #[test]
+    fn stored_normals_follow_transforms_into_the_output() {
+        let read_back = |m: &Mesh| {
+            let mut w = MemWriter::new();
+            StlFile::write_binary(m, &mut w, false, false).unwrap();
+            Mesh::read(&mut BufferedReader::new(MemReader::new(w.into_inner()))).unwrap()
+        };
+        let stl = binary_stl(&Mesh::cube(2.0));
+        let cube = Mesh::read(&mut BufferedReader::new(MemReader::new(stl))).unwrap();
+        assert!(cube.facets().iter().all(|f| f.stored_normal().is_some()));
+        let moved = cube.rotate(v(1.0, 1.0, 0.0), 0.7).scale(v(1.0, 3.0, 0.5))
+            .mirror(Axis::Z).flip_normals().flip_normals();
+        let written = read_back(&moved);
+        assert_eq!(written.facets().iter().map(|f| f.stored_normal()).collect::<Vec<_>>(),
+                   moved.facets().iter().map(|f| f.stored_normal()).collect::<Vec<_>>());
+        assert!(written.normal_mismatches(0.01).is_empty());
+
+        let plain = read_back(&triangle());
+        assert_eq!(plain.facets()[0].stored_normal(), Some(v(0.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn vector_operators() {
+        let a = v(1.0, -2.0, 3.0);
+        let b = v(4.0, 5.0, -6.0);
+        let zero = v(0.0, 0.0, 0.0);
+        assert_eq!(a + b, v(5.0, 3.0, -3.0));
+        assert_eq!(a - b, v(-3.0, -7.0, 9.0));
+        assert_eq!(a * 2.0, v(2.0, -4.0, 6.0));
+        assert_eq!(-a, v(-1.0, 2.0, -3.0));
+        assert_eq!(a + b, b + a);
+        assert_eq!(a - b, -(b - a));
+        assert_eq!(a + zero, a);
+        assert_eq!(a - a, zero);
+        assert_eq!(a + -a, zero);
+        assert_eq!(a * 1.0, a);
+        assert_eq!(a * -1.0, -a);
+        assert_eq!((a + b) * 2.0, a * 2.0 + b * 2.0);
+    }
+
+    #[test]
+    fn nan_vertices_are_read_found_and_dropped() {
+        let nan: f32 = Float::nan();
+        let cube = Mesh::cube(1.0);
+        let mut vs = cube.vertices().to_vec();
+        vs[0] = v(nan, 0.5, nan);
+        let broken = Mesh::new_from_parts(vs, cube.facets().to_vec());
+        let stl = binary_stl(&broken);
+        let read = Mesh::read(&mut BufferedReader::new(MemReader::new(stl))).unwrap();
+        assert_eq!(read.facet_count(), 12);
+        assert_eq!(read.vertex_count(), 8);
+        assert!(read.has_nan());
+        assert_eq!(read.invalid_vertices().len(), 1);
+
+        let cleaned = read.drop_invalid();
+        assert!(!cleaned.has_nan());
+        assert_eq!(cleaned.facet_count(), 6);
+        assert_eq!(cleaned.vertex_count(), 7);
+    }
+
+    #[test]
+    fn primitives_are_closed_and_face_outward() {
+        let pi: f32 = Float::pi();
+        let primitives = [
+            (Mesh::cube(2.0), 8.0),
+            (Mesh::sphere(1.0, 32), 4.0 / 3.0 * pi),
+            (Mesh::cylinder(1.0, 2.0, 32), 2.0 * pi),
+        ];
+        for &(ref mesh, exact) in primitives.iter() {
+            assert!(mesh.is_watertight());
+            assert!(mesh.boundary_loops().is_empty());
+            let volume = mesh.volume();
+            assert!(volume > 0.0);
+            assert!(volume <= exact * 1.0001 && volume > exact * 0.9);
+        }
+    }
+
+    #[test]
+    fn kdtree_nearest_matches_brute_force() {
+        // A fixed linear congruential sequence stands in for random points.
+        let mut seed = 12345u64;
+        let mut next = || {
+            seed = (seed * 1103515245 + 12345) % (1 << 31);
+            seed as f32 / (1u64 << 31) as f32 * 10.0 - 5.0
+        };
+        let points: Vec<Vector3D> = (0..500).map(|_| v(next(), next(), next())).collect();
+        let mesh = Mesh::new_from_parts(points, vec![]);
+        let tree = mesh.build_kdtree();
+        for _ in 0..200 {
+            let p = v(next(), next(), next());
+            let found = tree.nearest(p).unwrap();
+            let best = mesh.vertices().iter().map(|&q| (q - p).length())
+                .fold(Float::infinity(), |a: f32, b| a.min(b));
+            assert_eq!((mesh.vertices()[found] - p).length(), best);
+        }
+        for (i, &q) in mesh.vertices().iter().enumerate() {
+            assert_eq!(tree.nearest(q + v(1e-4, 0.0, 0.0)), Some(i));
+        }
+        assert_eq!(Mesh::new().build_kdtree().nearest(v(0.0, 0.0, 0.0)), None);
+    }
+
+    fn read_recovering(data: Vec<u8>) -> (Vec<Mesh>, Option<Shortfall>) {
+        Mesh::read_solids_recover(&mut BufferedReader::new(MemReader::new(data)), true,
+                                  ByteOrder::Little).unwrap()
+    }
+
+    fn read_strict(data: Vec<u8>) -> Result<Vec<Mesh>, MeshError> {
+        Mesh::read_solids(&mut BufferedReader::new(MemReader::new(data)), true, ByteOrder::Little)
+    }
+
+    #[test]
+    fn truncated_binary_keeps_the_whole_facets() {
+        let mut stl = binary_stl(&Mesh::cube(1.0));
+        stl.truncate(84 + 5 * 50 + 20);
+        assert_eq!(read_strict(stl.clone()).unwrap_err(), MeshError::TruncatedFacetData(5));
+        let (solids, shortfall) = read_recovering(stl);
+        assert_eq!(solids.len(), 1);
+        assert_eq!(solids[0].facet_count(), 5);
+        assert_eq!(shortfall, Some(Shortfall {
+            reason: MeshError::TruncatedFacetData(5),
+            kept: 5,
+            declared: Some(12),
+        }));
+    }
+
+    #[test]
+    fn truncated_ascii_keeps_the_whole_facets() {
+        let facet = |z: f32| format!(concat!(
+            " facet normal 0 0 1\n  outer loop\n",
+            "   vertex 0 0 {0}\n   vertex 1 0 {0}\n   vertex 0 1 {0}\n",
+            "  endloop\n endfacet\n"), z);
+        let mut text = format!("solid part\n{}{}", facet(0.0), facet(1.0));
+        text.push_str(" facet normal 0 0 1\n  outer loop\n   vertex 0 0 2\n");
+        let stl = text.into_bytes();
+        match read_strict(stl.clone()) {
+            Err(MeshError::Malformed(_)) => {}
+            other => panic!("expected a malformed file, got {:?}", other),
+        }
+        let (solids, shortfall) = read_recovering(stl);
+        assert_eq!(solids.len(), 1);
+        assert_eq!(solids[0].facet_count(), 2);
+        assert_eq!(solids[0].name(), Some("part"));
+        let shortfall = shortfall.unwrap();
+        assert_eq!((shortfall.kept, shortfall.declared), (2, None));
+    }
+
+    #[test]
+    fn bounding_sphere_encloses_every_vertex() {
+        let points = vec![v(0.0, 0.0, 0.0), v(4.0, 0.0, 0.0), v(2.0, 3.0, 0.0), v(2.0, 1.0, 5.0),
+                          v(-1.0, 2.0, 1.0), v(3.0, -2.0, -2.0)];
+        let meshes = [Mesh::new_from_parts(points, vec![]), Mesh::sphere(3.0, 16),
+                      Mesh::cube(2.0).translate(v(10.0, -4.0, 1.0))];
+        for mesh in meshes.iter() {
+            let (center, radius) = mesh.bounding_sphere();
+            for p in mesh.vertices().iter() {
+                assert!((*p - center).length() <= radius * 1.0001);
+            }
+        }
+        let (center, radius) = Mesh::cube(2.0).bounding_sphere();
+        assert!(near(center, v(0.0, 0.0, 0.0)));
+        assert!((radius - 3.0f32.sqrt()).abs() < 1e-5);
+    }
 
-        Mesh::new_from_stl(&facets, &vertices)
+    #[test]
+    fn empty_bounding_sphere_is_a_point_at_the_origin() {
+        assert_eq!(Mesh::new().bounding_sphere(), (v(0.0, 0.0, 0.0), 0.0));
     }
 }
@@ -0,0 +1,134 @@
+use std::fmt;
+
+use std::num::Float;
+
+use super::{Axis, Vector3D};
+
+// A 4x4 matrix acting on points as homogeneous column vectors, stored row
+// major: m[4 * row + col].
+#[derive(PartialEq, Copy, Clone)]
+pub struct Matrix4 {
+    m: [f32; 16],
+}
+
+impl fmt::Debug for Matrix4 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for r in 0..4 {
+            try!(write!(f, "{}[{} {} {} {}]", if r == 0 { "" } else { " " },
+                self.m[4 * r], self.m[4 * r + 1], self.m[4 * r + 2], self.m[4 * r + 3]));
+        }
+        Ok(())
+    }
+}
+
+impl Matrix4 {
+    pub fn new(m: [f32; 16]) -> Matrix4 {
+        Matrix4 { m: m }
+    }
+
+    pub fn identity() -> Matrix4 {
+        Matrix4::new([1.0, 0.0, 0.0, 0.0,
+                      0.0, 1.0, 0.0, 0.0,
+                      0.0, 0.0, 1.0, 0.0,
+                      0.0, 0.0, 0.0, 1.0])
+    }
+
+    pub fn translation(offset: Vector3D) -> Matrix4 {
+        Matrix4::new([1.0, 0.0, 0.0, offset.x,
+                      0.0, 1.0, 0.0, offset.y,
+                      0.0, 0.0, 1.0, offset.z,
+                      0.0, 0.0, 0.0, 1.0])
+    }
+
+    pub fn scaling(factors: Vector3D) -> Matrix4 {
+        Matrix4::new([factors.x, 0.0, 0.0, 0.0,
+                      0.0, factors.y, 0.0, 0.0,
+                      0.0, 0.0, factors.z, 0.0,
+                      0.0, 0.0, 0.0, 1.0])
+    }
+
+    // Rotation by angle radians about axis, matching Mesh::rotate.
+    pub fn rotation(axis: Vector3D, angle: f32) -> Matrix4 {
+        let k = axis.normalize();
+        let (s, c) = angle.sin_cos();
+        let t = 1.0 - c;
+        Matrix4::new([c + k.x * k.x * t, k.x * k.y * t - k.z * s, k.x * k.z * t + k.y * s, 0.0,
+                      k.y * k.x * t + k.z * s, c + k.y * k.y * t, k.y * k.z * t - k.x * s, 0.0,
+                      k.z * k.x * t - k.y * s, k.z * k.y * t + k.x * s, c + k.z * k.z * t, 0.0,
+                      0.0, 0.0, 0.0, 1.0])
+    }
+
+    // Reflection negating one coordinate, matching Mesh::mirror.
+    pub fn reflection(axis: Axis) -> Matrix4 {
+        Matrix4::scaling(match axis {
+            Axis::X => Vector3D { x: -1.0, y: 1.0, z: 1.0 },
+            Axis::Y => Vector3D { x: 1.0, y: -1.0, z: 1.0 },
+            Axis::Z => Vector3D { x: 1.0, y: 1.0, z: -1.0 },
+        })
+    }
+
+    fn at(&self, row: usize, col: usize) -> f32 {
+        self.m[4 * row + col]
+    }
+
+    // self * other, i.e. the transform that applies other first.
+    pub fn mul(&self, other: &Matrix4) -> Matrix4 {
+        let mut m = [0.0f32; 16];
+        for r in 0..4 {
+            for c in 0..4 {
+                m[4 * r + c] = (0..4).fold(0.0, |acc, k| acc + self.at(r, k) * other.at(k, c));
+            }
+        }
+        Matrix4::new(m)
+    }
+
+    // Transforms p as the point (x, y, z, 1). A projective bottom row is
+    // honoured by dividing through by w unless w comes out as zero.
+    pub fn transform_point(&self, p: &Vector3D) -> Vector3D {
+        let row = |r: usize| {
+            self.at(r, 0) * p.x + self.at(r, 1) * p.y + self.at(r, 2) * p.z + self.at(r, 3)
+        };
+        let w = row(3);
+        let w = if w == 0.0 { 1.0 } else { w };
+        Vector3D { x: row(0) / w, y: row(1) / w, z: row(2) / w }
+    }
+
+    fn minor3(&self, rows: [usize; 3], cols: [usize; 3]) -> f32 {
+        let a = |i: usize, j: usize| self.at(rows[i], cols[j]);
+        a(0, 0) * (a(1, 1) * a(2, 2) - a(1, 2) * a(2, 1))
+            - a(0, 1) * (a(1, 0) * a(2, 2) - a(1, 2) * a(2, 0))
+            + a(0, 2) * (a(1, 0) * a(2, 1) - a(1, 1) * a(2, 0))
+    }
+
+    pub fn determinant(&self) -> f32 {
+        let rows = [1, 2, 3];
+        self.at(0, 0) * self.minor3(rows, [1, 2, 3])
+            - self.at(0, 1) * self.minor3(rows, [0, 2, 3])
+            + self.at(0, 2) * self.minor3(rows, [0, 1, 3])
+            - self.at(0, 3) * self.minor3(rows, [0, 1, 2])
+    }
+
+    // Where a facet normal goes under the transform: the cofactor matrix of
+    // the upper-left 3x3 block takes the cross product of two edges to the
+    // cross product of the transformed edges. The result isn't unit length.
+    // None for a projective transform, under which no matrix does this.
+    pub fn transform_normal(&self, n: &Vector3D) -> Option<Vector3D> {
+        if self.at(3, 0) != 0.0 || self.at(3, 1) != 0.0 || self.at(3, 2) != 0.0 {
+            return None;
+        }
+        let cofactor = |r: usize, c: usize| {
+            let others = |i: usize| match i { 0 => (1, 2), 1 => (0, 2), _ => (0, 1) };
+            let ((r0, r1), (c0, c1)) = (others(r), others(c));
+            let minor = self.at(r0, c0) * self.at(r1, c1) - self.at(r0, c1) * self.at(r1, c0);
+            if (r + c) % 2 == 0 { minor } else { -minor }
+        };
+        let row = |r: usize| cofactor(r, 0) * n.x + cofactor(r, 1) * n.y + cofactor(r, 2) * n.z;
+        Some(Vector3D { x: row(0), y: row(1), z: row(2) })
+    }
+
+    // Determinant of the upper-left 3x3 block. Negative when the transform
+    // turns the mesh inside out, as a reflection does.
+    pub fn linear_determinant(&self) -> f32 {
+        self.minor3([0, 1, 2], [0, 1, 2])
+    }
+}
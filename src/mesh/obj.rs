@@ -0,0 +1,116 @@
+use std::old_io::{BufferedReader,BufferedWriter,IoResult,Reader,Writer};
+use std::old_io::fs::File;
+
+use super::{ByteOrder, Facet, Mesh, MeshError, Vector3D};
+
+pub struct ObjFile;
+
+impl ObjFile {
+    // Reads 'v' and 'f' lines into a mesh, triangulating larger faces as a
+    // fan around their first vertex. The first 'o' line names the mesh.
+    // Normals, texture coordinates, comments, groups, further objects and
+    // material statements are skipped.
+    pub fn read<R: Reader>(r: &mut BufferedReader<R>) -> Result<Mesh, MeshError> {
+        let mesh = try!(ObjFile::read_mesh(r).map_err(MeshError::Malformed));
+        diag!("Facets: {}", mesh.facets.len());
+        diag!("Vertices: {}", mesh.vertices.len());
+        Ok(mesh)
+    }
+
+    fn read_mesh<R: Reader>(r: &mut BufferedReader<R>) -> Result<Mesh, String> {
+        let mut vertices: Vec<Vector3D> = Vec::new();
+        let mut facets: Vec<Facet> = Vec::new();
+        let mut name: Option<String> = None;
+        let mut line_no = 0;
+        for line in r.lines() {
+            line_no += 1;
+            let line = match line {
+                Ok(l) => l,
+                Err(e) => return Err(format!("read error: {}", e)),
+            };
+            let tokens: Vec<&str> = line.words().collect();
+            if tokens.is_empty() {
+                continue;
+            }
+            match tokens[0] {
+                "v" => {
+                    if tokens.len() < 4 {
+                        return Err(format!("line {}: vertex needs 3 coordinates", line_no));
+                    }
+                    let mut c = [0.0f32; 3];
+                    for i in 0..3 {
+                        c[i] = match tokens[i + 1].parse::<f32>() {
+                            Ok(f) => f,
+                            Err(_) => return Err(format!("line {}: bad number '{}'",
+                                line_no, tokens[i + 1])),
+                        };
+                    }
+                    vertices.push(Vector3D { x: c[0], y: c[1], z: c[2] });
+                }
+                "f" => {
+                    if tokens.len() < 4 {
+                        return Err(format!("line {}: face needs at least 3 vertices", line_no));
+                    }
+                    let mut idx: Vec<usize> = Vec::with_capacity(tokens.len() - 1);
+                    for t in tokens[1..].iter() {
+                        idx.push(try!(ObjFile::face_index(t, vertices.len(), line_no)));
+                    }
+                    for i in 1..idx.len() - 1 {
+                        facets.push(Facet::new(idx[0], idx[i], idx[i + 1]));
+                    }
+                }
+                "o" if name.is_none() && tokens.len() > 1 => name = Some(tokens[1..].connect(" ")),
+                _ => {}
+            }
+        }
+        Ok(Mesh {
+            vertices: vertices,
+            facets: facets,
+            header: None,
+            byte_order: ByteOrder::Little,
+            name: name,
+            colors: None,
+        })
+    }
+
+    // Resolves one face token ("7", "7/2", "7//3" or a negative relative
+    // index) to a 0-based vertex index.
+    fn face_index(token: &str, vertex_count: usize, line_no: usize) -> Result<usize, String> {
+        let vi = token.split('/').next().unwrap_or("");
+        let i = match vi.parse::<isize>() {
+            Ok(i) => i,
+            Err(_) => return Err(format!("line {}: bad face index '{}'", line_no, token)),
+        };
+        let resolved = if i < 0 { vertex_count as isize + i } else { i - 1 };
+        if i == 0 || resolved < 0 || resolved >= vertex_count as isize {
+            return Err(format!("line {}: face index {} out of range", line_no, i));
+        }
+        Ok(resolved as usize)
+    }
+
+    // Wavefront OBJ maps directly onto our indexed mesh: one 'v' line per
+    // vertex and one 'f' line per facet. OBJ indices start at 1. With
+    // recompute_normals each facet also gets its own 'vn' line.
+    pub fn write(mesh: &Mesh, path: &str, recompute_normals: bool) -> IoResult<()> {
+        let file = try!(File::create(&Path::new(path)));
+        let mut w = BufferedWriter::new(file);
+        try!(write!(&mut w, "o {}\n", mesh.label()));
+        for v in mesh.vertices.iter() {
+            try!(write!(&mut w, "v {} {} {}\n", v.x, v.y, v.z));
+        }
+        if recompute_normals {
+            for n in mesh.recomputed_normals().iter() {
+                try!(write!(&mut w, "vn {} {} {}\n", n.x, n.y, n.z));
+            }
+            for (i, f) in mesh.facets.iter().enumerate() {
+                try!(write!(&mut w, "f {}//{} {}//{} {}//{}\n",
+                    f.v1 + 1, i + 1, f.v2 + 1, i + 1, f.v3 + 1, i + 1));
+            }
+        } else {
+            for f in mesh.facets.iter() {
+                try!(write!(&mut w, "f {} {} {}\n", f.v1 + 1, f.v2 + 1, f.v3 + 1));
+            }
+        }
+        w.flush()
+    }
+}
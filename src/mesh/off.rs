@@ -0,0 +1,25 @@
+use std::old_io::{BufferedWriter,IoResult,Writer};
+use std::old_io::fs::File;
+
+use super::Mesh;
+
+pub struct OffFile;
+
+impl OffFile {
+    // Object File Format as read by Geomview and CGAL: the magic line, the
+    // vertex/face/edge counts, then vertices and faces. OFF indices are
+    // 0-based like ours, and the edge count is customarily written as 0.
+    pub fn write(mesh: &Mesh, path: &str) -> IoResult<()> {
+        let file = try!(File::create(&Path::new(path)));
+        let mut w = BufferedWriter::new(file);
+        try!(w.write_str("OFF\n"));
+        try!(write!(&mut w, "{} {} 0\n", mesh.vertices.len(), mesh.facets.len()));
+        for v in mesh.vertices.iter() {
+            try!(write!(&mut w, "{} {} {}\n", v.x, v.y, v.z));
+        }
+        for f in mesh.facets.iter() {
+            try!(write!(&mut w, "3 {} {} {}\n", f.v1, f.v2, f.v3));
+        }
+        w.flush()
+    }
+}
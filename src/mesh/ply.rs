@@ -0,0 +1,68 @@
+use std::old_io::{BufferedWriter,IoResult,Writer};
+use std::old_io::fs::File;
+
+use super::Mesh;
+
+pub struct PlyFile;
+
+impl PlyFile {
+    // Stanford PLY holds the same indexed vertex/face layout as our mesh.
+    // The header is always ASCII; the body is either one line per element or
+    // packed little-endian values, with each face as a uchar count followed
+    // by that many int indices. Vertex colors, if the mesh has them, follow
+    // the coordinates as uchar red, green and blue.
+    pub fn write(mesh: &Mesh, path: &str, binary: bool) -> IoResult<()> {
+        let file = try!(File::create(&Path::new(path)));
+        let mut w = BufferedWriter::new(file);
+        try!(w.write_str("ply\n"));
+        try!(w.write_str(if binary {
+            "format binary_little_endian 1.0\n"
+        } else {
+            "format ascii 1.0\n"
+        }));
+        try!(w.write_str("comment written by meshman\n"));
+        try!(write!(&mut w, "element vertex {}\n", mesh.vertices.len()));
+        try!(w.write_str("property float x\n"));
+        try!(w.write_str("property float y\n"));
+        try!(w.write_str("property float z\n"));
+        let colors = mesh.colors();
+        if colors.is_some() {
+            try!(w.write_str("property uchar red\n"));
+            try!(w.write_str("property uchar green\n"));
+            try!(w.write_str("property uchar blue\n"));
+        }
+        try!(write!(&mut w, "element face {}\n", mesh.facets.len()));
+        try!(w.write_str("property list uchar int vertex_indices\n"));
+        try!(w.write_str("end_header\n"));
+        if binary {
+            for (i, v) in mesh.vertices.iter().enumerate() {
+                try!(w.write_le_f32(v.x));
+                try!(w.write_le_f32(v.y));
+                try!(w.write_le_f32(v.z));
+                if let Some(c) = colors {
+                    try!(w.write_u8(c[i].r));
+                    try!(w.write_u8(c[i].g));
+                    try!(w.write_u8(c[i].b));
+                }
+            }
+            for f in mesh.facets.iter() {
+                try!(w.write_u8(3));
+                try!(w.write_le_i32(f.v1 as i32));
+                try!(w.write_le_i32(f.v2 as i32));
+                try!(w.write_le_i32(f.v3 as i32));
+            }
+        } else {
+            for (i, v) in mesh.vertices.iter().enumerate() {
+                match colors {
+                    Some(c) => try!(write!(&mut w, "{} {} {} {} {} {}\n",
+                                           v.x, v.y, v.z, c[i].r, c[i].g, c[i].b)),
+                    None => try!(write!(&mut w, "{} {} {}\n", v.x, v.y, v.z)),
+                }
+            }
+            for f in mesh.facets.iter() {
+                try!(write!(&mut w, "3 {} {} {}\n", f.v1, f.v2, f.v3));
+            }
+        }
+        w.flush()
+    }
+}
@@ -0,0 +1,29 @@
+use std::old_io::{BufferedWriter,IoResult,Writer};
+use std::old_io::fs::File;
+
+use super::Mesh;
+
+pub struct PovFile;
+
+impl PovFile {
+    // POV-Ray's mesh2 object is indexed like our mesh: a vertex_vectors
+    // list and face_indices referring into it by 0-based index, each
+    // preceded by its length. The object is #declared as meshman_mesh so a
+    // scene can include the file and place it with an object statement.
+    pub fn write_mesh2(mesh: &Mesh, path: &str) -> IoResult<()> {
+        let file = try!(File::create(&Path::new(path)));
+        let mut w = BufferedWriter::new(file);
+        try!(w.write_str("// Written by meshman\n"));
+        try!(w.write_str("#declare meshman_mesh = mesh2 {\n"));
+        try!(write!(&mut w, "  vertex_vectors {{\n    {}", mesh.vertices.len()));
+        for v in mesh.vertices.iter() {
+            try!(write!(&mut w, ",\n    <{}, {}, {}>", v.x, v.y, v.z));
+        }
+        try!(write!(&mut w, "\n  }}\n  face_indices {{\n    {}", mesh.facets.len()));
+        for f in mesh.facets.iter() {
+            try!(write!(&mut w, ",\n    <{}, {}, {}>", f.v1, f.v2, f.v3));
+        }
+        try!(w.write_str("\n  }\n}\n"));
+        w.flush()
+    }
+}
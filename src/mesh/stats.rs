@@ -0,0 +1,30 @@
+use std::num::Float;
+
+use super::{Mesh, Vector3D};
+
+// JSON has no NaN or infinity, so non-finite values become null.
+fn json_number(f: f32) -> String {
+    if f.is_finite() { format!("{}", f) } else { "null".to_string() }
+}
+
+fn json_vector(v: &Vector3D) -> String {
+    format!("[{}, {}, {}]", json_number(v.x), json_number(v.y), json_number(v.z))
+}
+
+// Mesh statistics as a JSON object for scripts, with the same numbers the
+// text reports print. bounding_box is null for an empty mesh.
+pub fn stats_json(mesh: &Mesh) -> String {
+    let bbox = match mesh.bounding_box() {
+        Some((min, max)) => format!("{{\"min\": {}, \"max\": {}}}",
+            json_vector(&min), json_vector(&max)),
+        None => "null".to_string(),
+    };
+    format!("{{\n  \"vertex_count\": {},\n  \"facet_count\": {},\n  \"bounding_box\": {},\n  \
+             \"volume\": {},\n  \"surface_area\": {},\n  \"watertight\": {}\n}}",
+        mesh.vertices.len(),
+        mesh.facets.len(),
+        bbox,
+        json_number(mesh.volume().abs()),
+        json_number(mesh.surface_area()),
+        mesh.is_watertight())
+}
@@ -0,0 +1,45 @@
+use std::old_io::{BufferedWriter,IoResult,Writer};
+use std::old_io::fs::File;
+use std::num::Float;
+
+use super::Mesh;
+
+// Blank space left around the drawing, in pixels.
+const MARGIN: f32 = 10.0;
+
+pub struct SvgFile;
+
+impl SvgFile {
+    // Draws the mesh seen from above: every edge projected onto the XY
+    // plane, scaled so the larger side of the bounding box fills a canvas
+    // of size pixels. SVG's y axis points down, so y is flipped to keep +Y
+    // at the top. Each edge is drawn once however many facets share it.
+    pub fn write(mesh: &Mesh, path: &str, size: u32) -> IoResult<()> {
+        let file = try!(File::create(&Path::new(path)));
+        let mut w = BufferedWriter::new(file);
+        let (min, max) = match mesh.bounding_box() {
+            Some(b) => b,
+            None => {
+                try!(write!(&mut w, "<svg xmlns=\"http://www.w3.org/2000/svg\" \
+                    width=\"{0}\" height=\"{0}\"/>\n", size));
+                return w.flush();
+            }
+        };
+        let extent = (max.x - min.x).max(max.y - min.y);
+        let inner = size as f32 - 2.0 * MARGIN;
+        let scale = if extent > 0.0 { inner / extent } else { 1.0 };
+        let width = (max.x - min.x) * scale + 2.0 * MARGIN;
+        let height = (max.y - min.y) * scale + 2.0 * MARGIN;
+        try!(write!(&mut w, "<svg xmlns=\"http://www.w3.org/2000/svg\" \
+            width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">\n", width, height));
+        try!(w.write_str("<path fill=\"none\" stroke=\"black\" stroke-width=\"0.5\" d=\""));
+        for &(a, b) in mesh.edge_counts().keys() {
+            let (va, vb) = (&mesh.vertices[a], &mesh.vertices[b]);
+            try!(write!(&mut w, "M{} {}L{} {}",
+                (va.x - min.x) * scale + MARGIN, (max.y - va.y) * scale + MARGIN,
+                (vb.x - min.x) * scale + MARGIN, (max.y - vb.y) * scale + MARGIN));
+        }
+        try!(w.write_str("\"/>\n</svg>\n"));
+        w.flush()
+    }
+}
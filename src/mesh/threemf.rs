@@ -0,0 +1,198 @@
+use std::old_io::{BufferedWriter,IoResult,Writer};
+use std::old_io::fs::File;
+
+use super::Mesh;
+use deflate::crc32;
+
+static CONTENT_TYPES: &'static str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\">
+ <Default Extension=\"rels\" ContentType=\"application/vnd.openxmlformats-package.relationships+xml\"/>
+ <Default Extension=\"model\" ContentType=\"application/vnd.ms-package.3dmanufacturing-3dmodel+xml\"/>
+</Types>
+";
+
+static RELS: &'static str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">
+ <Relationship Target=\"/3D/3dmodel.model\" Id=\"rel0\" Type=\"http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel\"/>
+</Relationships>
+";
+
+pub struct ThreeMfFile;
+
+impl ThreeMfFile {
+    // A 3MF file is a ZIP package holding the content types, a relationship
+    // pointing at the model part, and the model XML itself. The parts are
+    // small enough to build in memory and are stored uncompressed, which
+    // every ZIP reader accepts.
+    pub fn write(mesh: &Mesh, path: &str) -> IoResult<()> {
+        let file = try!(File::create(&Path::new(path)));
+        ThreeMfFile::write_to(mesh, &mut BufferedWriter::new(file))
+    }
+
+    fn write_to<W: Writer>(mesh: &Mesh, w: &mut W) -> IoResult<()> {
+        let model = ThreeMfFile::model(mesh);
+        let mut zip = ZipWriter::new(w);
+        try!(zip.add("[Content_Types].xml", CONTENT_TYPES.as_bytes()));
+        try!(zip.add("_rels/.rels", RELS.as_bytes()));
+        try!(zip.add("3D/3dmodel.model", model.as_bytes()));
+        zip.finish()
+    }
+
+    // 3MF units default to millimeters, which is what STL files are almost
+    // always drawn in. Triangle indices are 0-based like ours.
+    fn model(mesh: &Mesh) -> String {
+        let mut s = String::new();
+        s.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        s.push_str("<model unit=\"millimeter\" xml:lang=\"en-US\" \
+                    xmlns=\"http://schemas.microsoft.com/3dmanufacturing/core/2015/02\">\n");
+        s.push_str(format!(" <resources>\n  <object id=\"1\" type=\"model\" name=\"{}\">\n",
+            escape(mesh.label())).as_slice());
+        s.push_str("   <mesh>\n    <vertices>\n");
+        for v in mesh.vertices.iter() {
+            s.push_str(format!("     <vertex x=\"{}\" y=\"{}\" z=\"{}\"/>\n",
+                v.x, v.y, v.z).as_slice());
+        }
+        s.push_str("    </vertices>\n    <triangles>\n");
+        for f in mesh.facets.iter() {
+            s.push_str(format!("     <triangle v1=\"{}\" v2=\"{}\" v3=\"{}\"/>\n",
+                f.v1, f.v2, f.v3).as_slice());
+        }
+        s.push_str("    </triangles>\n   </mesh>\n  </object>\n </resources>\n");
+        s.push_str(" <build>\n  <item objectid=\"1\"/>\n </build>\n</model>\n");
+        s
+    }
+}
+
+// Escapes text for use in an XML attribute value or element.
+pub fn escape(text: &str) -> String {
+    let mut s = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => s.push_str("&amp;"),
+            '<' => s.push_str("&lt;"),
+            '>' => s.push_str("&gt;"),
+            '"' => s.push_str("&quot;"),
+            _ => s.push(c),
+        }
+    }
+    s
+}
+
+// Just enough of the ZIP format to write stored (uncompressed) entries:
+// a local header and the data for each entry, then the central directory
+// and the end record.
+struct ZipWriter<'a, W: 'a> {
+    w: &'a mut W,
+    offset: u32,
+    entries: Vec<(String, u32, u32, u32)>,  // name, crc, size, header offset
+}
+
+// MS-DOS date for 1980-01-01, the earliest a ZIP timestamp can hold.
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+impl<'a, W: Writer> ZipWriter<'a, W> {
+    fn new(w: &'a mut W) -> ZipWriter<'a, W> {
+        ZipWriter { w: w, offset: 0, entries: Vec::new() }
+    }
+
+    fn add(&mut self, name: &str, data: &[u8]) -> IoResult<()> {
+        let crc = crc32(data);
+        let size = data.len() as u32;
+        try!(self.w.write_le_u32(0x04034b50));
+        try!(self.w.write_le_u16(20));          // version needed
+        try!(self.w.write_le_u16(0));           // flags
+        try!(self.w.write_le_u16(0));           // method: stored
+        try!(self.w.write_le_u16(0));           // time
+        try!(self.w.write_le_u16(DOS_DATE));
+        try!(self.w.write_le_u32(crc));
+        try!(self.w.write_le_u32(size));        // compressed
+        try!(self.w.write_le_u32(size));        // uncompressed
+        try!(self.w.write_le_u16(name.len() as u16));
+        try!(self.w.write_le_u16(0));           // extra field length
+        try!(self.w.write_str(name));
+        try!(self.w.write_all(data));
+        self.entries.push((name.to_string(), crc, size, self.offset));
+        self.offset += 30 + name.len() as u32 + size;
+        Ok(())
+    }
+
+    fn finish(mut self) -> IoResult<()> {
+        let cd_offset = self.offset;
+        let mut cd_size = 0u32;
+        for &(ref name, crc, size, offset) in self.entries.iter() {
+            try!(self.w.write_le_u32(0x02014b50));
+            try!(self.w.write_le_u16(20));      // version made by
+            try!(self.w.write_le_u16(20));      // version needed
+            try!(self.w.write_le_u16(0));       // flags
+            try!(self.w.write_le_u16(0));       // method: stored
+            try!(self.w.write_le_u16(0));       // time
+            try!(self.w.write_le_u16(DOS_DATE));
+            try!(self.w.write_le_u32(crc));
+            try!(self.w.write_le_u32(size));
+            try!(self.w.write_le_u32(size));
+            try!(self.w.write_le_u16(name.len() as u16));
+            try!(self.w.write_le_u16(0));       // extra field length
+            try!(self.w.write_le_u16(0));       // comment length
+            try!(self.w.write_le_u16(0));       // disk number
+            try!(self.w.write_le_u16(0));       // internal attributes
+            try!(self.w.write_le_u32(0));       // external attributes
+            try!(self.w.write_le_u32(offset));
+            try!(self.w.write_str(name.as_slice()));
+            cd_size += 46 + name.len() as u32;
+        }
+        let count = self.entries.len() as u16;
+        try!(self.w.write_le_u32(0x06054b50));
+        try!(self.w.write_le_u16(0));           // this disk
+        try!(self.w.write_le_u16(0));           // central directory disk
+        try!(self.w.write_le_u16(count));
+        try!(self.w.write_le_u16(count));
+        try!(self.w.write_le_u32(cd_size));
+        try!(self.w.write_le_u32(cd_offset));
+        try!(self.w.write_le_u16(0));           // comment length
+        self.w.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::old_io::MemWriter;
+    use super::{ThreeMfFile, CONTENT_TYPES, RELS};
+    use super::super::Mesh;
+    use inflate::unzip_first;
+
+    #[test]
+    fn package_reads_back() {
+        let cube = Mesh::cube(1.0);
+        let mut w = MemWriter::new();
+        ThreeMfFile::write_to(&cube, &mut w).unwrap();
+        let zip = w.into_inner();
+        let u16_at = |i: usize| zip[i] as u32 | (zip[i + 1] as u32) << 8;
+        let u32_at = |i: usize| u16_at(i) | u16_at(i + 2) << 16;
+
+        let eocd = zip.len() - 22;
+        assert_eq!(u32_at(eocd), 0x06054b50);
+        assert_eq!(u16_at(eocd + 8), 3);
+        assert_eq!(u16_at(eocd + 10), 3);
+        let cd_size = u32_at(eocd + 12) as usize;
+        let cd_offset = u32_at(eocd + 16) as usize;
+        assert_eq!(cd_offset + cd_size, eocd);
+
+        let model = ThreeMfFile::model(&cube);
+        let parts = [("[Content_Types].xml", CONTENT_TYPES),
+                     ("_rels/.rels", RELS),
+                     ("3D/3dmodel.model", model.as_slice())];
+        let mut pos = cd_offset;
+        for &(name, data) in parts.iter() {
+            assert_eq!(u32_at(pos), 0x02014b50);
+            let name_len = u16_at(pos + 28) as usize;
+            assert_eq!(&zip[pos + 46..pos + 46 + name_len], name.as_bytes());
+            let offset = u32_at(pos + 42) as usize;
+            assert_eq!(u32_at(offset), 0x04034b50);
+            assert_eq!(&zip[offset + 30..offset + 30 + name_len], name.as_bytes());
+            assert_eq!(unzip_first(&zip[offset..]).unwrap(), data.as_bytes().to_vec());
+            pos += 46 + name_len;
+        }
+        assert_eq!(pos, eocd);
+        assert_eq!(unzip_first(zip.as_slice()).unwrap(), CONTENT_TYPES.as_bytes().to_vec());
+    }
+}